@@ -22,17 +22,8 @@ impl Default for Camera {
 
 impl Camera {
     pub fn view_projection(&self, transform: WorldTransform) -> glam::Mat4 {
-        let WorldTransform { position, rotation } = transform;
-        let yaw = rotation.x.to_radians();
-        let pitch = rotation.y.to_radians();
-        let direction = glam::vec3(
-            yaw.cos() * pitch.cos(),
-            pitch.sin(),
-            yaw.sin() * pitch.cos(),
-        );
-        let front = direction.normalize();
-
-        let view = glam::Mat4::look_at_lh(position, position + front, glam::Vec3::Y);
+        let position = transform.position;
+        let view = glam::Mat4::look_at_lh(position, position + transform.front(), glam::Vec3::Y);
         self.projection() * view
     }
 
@@ -62,9 +62,9 @@ impl Input {
     //     self.mouse_state.pressed.contains(&mouse_code)
     // }
 
-    // pub fn is_mouse_just_pressed(&self, mouse_code: ButtonId) -> bool {
-    //     self.mouse_state.just_pressed.contains(&mouse_code)
-    // }
+    pub fn is_mouse_just_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_state.just_pressed.contains(&button)
+    }
 
     // pub fn is_mouse_just_released(&self, mouse_code: ButtonId) -> bool {
     //     self.mouse_state.just_released.contains(&mouse_code)
@@ -6,23 +6,55 @@ mod time;
 mod window;
 mod world;
 
-use bevy_app::App;
-use opencuboids_common::DEFAULT_PORT;
+use std::sync::{Arc, Mutex};
+
+use bevy_app::{App, AppExit};
+use bevy_ecs::prelude::*;
+use opencuboids_common::{BlockRegistry, DEFAULT_PORT};
+use opencuboids_server::{Server, WorldSave};
+
+/// The world save of the server running inside of the client
+#[derive(Resource)]
+struct LocalWorldSave(Arc<Mutex<WorldSave>>);
+
+fn save_on_exit(mut exit_event: EventReader<AppExit>, world_save: Option<Res<LocalWorldSave>>) {
+    if exit_event.iter().next().is_some() {
+        if let Some(world_save) = world_save {
+            if let Err(err) = world_save.0.lock().unwrap().flush() {
+                log::error!("Failed to save world - {}", err);
+            }
+        }
+    }
+}
 
 fn main() {
     opencuboids_common::log_setup();
     let address = format!("0.0.0.0:{}", DEFAULT_PORT).parse().unwrap();
-    std::thread::spawn(move || opencuboids_server::start(address));
 
-    let channel = network::connect(address);
+    let mut app = App::new();
+    match Server::bind(address, "world", BlockRegistry::default()) {
+        Ok(server) => {
+            app.insert_resource(LocalWorldSave(server.world_save()));
+            std::thread::spawn(move || {
+                if let Err(err) = server.run() {
+                    log::error!("Server stopped - {}", err);
+                }
+            });
+        }
+        Err(err) => log::error!("Failed to start server on {} - {}", address, err),
+    }
+
+    let player_name = std::env::var("OPENCUBOIDS_NAME").unwrap_or_else(|_| "Player".to_owned());
+    let channel = network::connect(address, player_name);
 
-    App::new()
-        .add_plugin(window::Plugin)
+    app.add_plugin(window::Plugin)
         .add_plugin(render::Plugin)
         .add_plugin(time::Plugin)
         .add_plugin(input::Plugin)
         .add_plugin(world::Plugin)
         .add_system(network::handle_responses)
+        .add_system(network::send_transform)
+        .add_system_to_stage(bevy_app::CoreStage::Last, save_on_exit)
         .insert_resource(channel)
         .run();
 }
@@ -1,11 +1,16 @@
 use bevy_ecs::prelude::*;
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
 
-use bevy_utils::Duration;
+use bevy_utils::{Duration, Instant};
 use crossbeam_channel::{Receiver, Sender};
 use opencuboids_common::network;
 
-use crate::world::ChunkManager;
+use crate::{
+    time::Time,
+    world::{
+        Blocks, ChunkManager, PhysicsBody, Player, RemotePlayer, RemotePlayers, WorldTransform,
+    },
+};
 
 #[derive(Resource)]
 pub struct StreamChannel {
@@ -13,14 +18,22 @@ pub struct StreamChannel {
     pub receiver: Receiver<network::Response>,
 }
 
-pub fn connect(address: SocketAddr) -> StreamChannel {
-    log::info!("Connecting to {}", address);
+pub fn connect(address: SocketAddr, player_name: String) -> StreamChannel {
+    log::info!("Connecting to {} as {}", address, player_name);
     let (request_tx, request_rx) = crossbeam_channel::unbounded();
     let (response_tx, response_rx) = crossbeam_channel::unbounded();
 
     std::thread::spawn(move || match network::Protocol::connect(address, 5) {
-        Ok(client) => {
-            if let Err(err) = handle_client(client, response_tx, request_rx) {
+        Ok(mut client) => {
+            let result = client.handshake(&player_name).and_then(|(info, registry)| {
+                response_tx.send(network::Response::Welcome(info)).unwrap();
+                response_tx
+                    .send(network::Response::BlockRegistry(registry))
+                    .unwrap();
+                handle_client(client, response_tx, request_rx)
+            });
+
+            if let Err(err) = result {
                 log::error!("Error with connection, disconnecting - {}", err);
             }
         }
@@ -49,24 +62,101 @@ fn handle_client(
 
         match protocol.read::<network::Response>() {
             Ok(response) => sender.send(response).unwrap(),
-            Err(err) => match *err {
-                network::ErrorKind::Io(ref e) => match e.kind() {
-                    std::io::ErrorKind::WouldBlock => (),
-                    _ => Err(err)?,
-                },
-                _ => Err(err)?,
-            },
+            Err(err) if err.is_timeout() => (),
+            Err(err) => return Err(err),
         }
     }
 }
 
-pub fn handle_responses(channel: ResMut<StreamChannel>, mut chunk_manager: ResMut<ChunkManager>) {
+/// How often the server gets told where the player is
+const TRANSFORM_INTERVAL: Duration = Duration::from_millis(50);
+
+pub fn send_transform(
+    channel: Res<StreamChannel>,
+    time: Res<Time>,
+    blocks: Option<Res<Blocks>>,
+    mut since_sent: Local<Duration>,
+    player_query: Query<&WorldTransform, With<Player>>,
+) {
+    // The registry comes after the welcome, so the player isn't at the spawn position until then
+    if blocks.is_none() {
+        return;
+    }
+
+    *since_sent += time.delta;
+    if *since_sent < TRANSFORM_INTERVAL {
+        return;
+    }
+
+    *since_sent = Duration::ZERO;
+    let transform = player_query.single();
+    let _ = channel.sender.send(network::Request::PlayerTransform {
+        position: transform.position,
+        rotation: transform.rotation,
+    });
+}
+
+pub fn handle_responses(
+    mut commands: Commands,
+    channel: ResMut<StreamChannel>,
+    mut chunk_manager: ResMut<ChunkManager>,
+    mut remote_players: ResMut<RemotePlayers>,
+    mut remote_query: Query<&mut RemotePlayer>,
+    mut player_query: Query<(&mut WorldTransform, &mut PhysicsBody), With<Player>>,
+) {
     for response in channel.receiver.try_iter() {
         match response {
+            network::Response::Disconnect(reason) => {
+                log::error!("Disconnected by server - {}", reason);
+            }
+            network::Response::Welcome(info) => {
+                log::info!("Joined server with world seed {}", info.world_seed);
+                player_query.single_mut().0.position = info.spawn_position;
+            }
+            network::Response::BlockRegistry(registry) => {
+                commands.insert_resource(Blocks(Arc::new(registry)));
+            }
+            network::Response::ColumnBiomes { column, biomes } => {
+                chunk_manager.handle_column_biomes(column, biomes);
+            }
             network::Response::ChunkData(chunk) => {
-                chunk_manager.handle_chunk_response(chunk);
+                chunk_manager.handle_chunk_response(*chunk);
+            }
+            network::Response::BlockUpdate { pos, id } => {
+                chunk_manager.handle_block_update(pos, id);
+            }
+            network::Response::LightUpdate(lights) => {
+                chunk_manager.handle_light_update(lights);
+            }
+            network::Response::SpawnEntity {
+                id,
+                name,
+                position,
+                rotation,
+            } => {
+                remote_players.spawn(&mut commands, id, name, position, rotation);
+            }
+            network::Response::MoveEntity {
+                id,
+                position,
+                rotation,
+            } => {
+                if let Some(mut remote) = remote_players
+                    .get(id)
+                    .and_then(|entity| remote_query.get_mut(entity).ok())
+                {
+                    remote.push_snapshot(Instant::now(), position, rotation);
+                }
+            }
+            network::Response::DespawnEntity(id) => {
+                remote_players.despawn(&mut commands, id);
+            }
+            network::Response::SetPosition { position } => {
+                log::warn!("Moved back to {} by the server", position);
+                let (mut transform, mut body) = player_query.single_mut();
+                transform.position = position;
+                body.velocity = glam::Vec3::ZERO;
             }
-            _ => (),
         }
     }
 }
@@ -22,7 +22,6 @@ impl<T: bytemuck::Pod> Buffer<T> {
 
 pub struct DynamicBuffer<T> {
     pub buf: wgpu::Buffer,
-    pub max_len: usize,
     _marker: std::marker::PhantomData<T>,
 }
 
@@ -35,7 +34,6 @@ impl<T: bytemuck::Pod> DynamicBuffer<T> {
                 size: (max_len * std::mem::size_of::<T>()) as u64,
                 usage,
             }),
-            max_len,
             _marker: std::marker::PhantomData,
         }
     }
@@ -45,14 +43,23 @@ impl<T: bytemuck::Pod> DynamicBuffer<T> {
     }
 }
 
-/// Creates index buffer that indexes to make a quad (2 triangles)
-pub fn new_buffer_quad_index(device: &wgpu::Device, quad_count: usize) -> Buffer<u16> {
-    let indicies = [0, 1, 2, 2, 3, 0]
+/// Indices that make each group of 4 vertices into a quad (2 triangles), 32-bit so the
+/// vertices of more than 16384 quads can be indexed
+pub fn quad_indices(quad_count: usize) -> Vec<u32> {
+    [0, 1, 2, 2, 3, 0]
         .iter()
         .cycle()
         .take(quad_count * 6)
         .enumerate()
-        .map(|(i, quad_i)| (i / 6 * 4 + quad_i) as u16)
-        .collect::<Vec<_>>();
-    Buffer::new(device, wgpu::BufferUsages::INDEX, indicies.as_slice())
+        .map(|(i, quad_i)| (i / 6 * 4 + quad_i) as u32)
+        .collect()
+}
+
+/// Creates index buffer that indexes to make a quad (2 triangles)
+pub fn new_buffer_quad_index(device: &wgpu::Device, quad_count: usize) -> Buffer<u32> {
+    Buffer::new(
+        device,
+        wgpu::BufferUsages::INDEX,
+        quad_indices(quad_count).as_slice(),
+    )
 }
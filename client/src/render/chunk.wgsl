@@ -2,6 +2,7 @@ struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) uvs: vec2<f32>,
     @location(1) light_level: f32,
+    @location(2) @interpolate(flat) texture_layer: u32,
 };
 
 struct GlobalUniform {
@@ -13,17 +14,41 @@ var<uniform> global: GlobalUniform;
 
 var<push_constant> block_offset: vec3<f32>;
 
-var<private> uvs: array<vec2<f32>, 4> = array<vec2<f32>, 4>(
-    vec2<f32>(0.0, 0.0),
-    vec2<f32>(1.0, 0.0),
-    vec2<f32>(1.0, 1.0),
-    vec2<f32>(0.0, 1.0),
+// Uses a direction index to get the axes that the texture coordinates go along, the texture
+// repeats so it tiles across faces covering multiple blocks
+var<private> u_axes: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
+    vec3<f32>(-1.0, 0.0, 0.0),
+    vec3<f32>(1.0, 0.0, 0.0),
+    vec3<f32>(0.0, 0.0, 1.0),
+    vec3<f32>(0.0, 0.0, -1.0),
+    vec3<f32>(-1.0, 0.0, 0.0),
+    vec3<f32>(1.0, 0.0, 0.0),
+);
+var<private> v_axes: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
+    vec3<f32>(0.0, -1.0, 0.0),
+    vec3<f32>(0.0, -1.0, 0.0),
+    vec3<f32>(0.0, -1.0, 0.0),
+    vec3<f32>(0.0, -1.0, 0.0),
+    vec3<f32>(0.0, 0.0, 1.0),
+    vec3<f32>(0.0, 0.0, 1.0),
 );
 
 var<private> light_levels: array<f32, 6> = array<f32, 6>(0.8, 0.8, 0.6, 0.6, 1.0, 0.4);
 
+// Uses the ambient occlusion of the vertex from 0 (most occluded) to 3 to get how much it darkens
+var<private> ao_levels: array<f32, 4> = array<f32, 4>(0.45, 0.65, 0.85, 1.0);
+
+// Uses the highest of the sky and block light from 0 to 15 to get how bright the vertex is, each
+// level is darker than the last by more the darker it gets with a bit of light left in the dark
+fn light_brightness(light: u32) -> f32 {
+    let sky = f32(light & 0x3fu) / 4.0;
+    let block = f32((light & 0xfc0u) >> 6u) / 4.0;
+    let level = max(sky, block) / 15.0;
+    return mix(0.05, 1.0, level / (4.0 - 3.0 * level));
+}
+
 @vertex
-fn vs_main(@location(0) vertex: u32) -> VertexOutput {
+fn vs_main(@location(0) vertex: u32, @location(1) light: u32) -> VertexOutput {
     var out: VertexOutput;
 
     // Unpack vertex data
@@ -33,22 +58,23 @@ fn vs_main(@location(0) vertex: u32) -> VertexOutput {
     let position = vec3<f32>(f32(x), f32(y), f32(z));
     out.position = global.view_projection * vec4<f32>(position + block_offset, 1.0);
 
-    let uv_index = (vertex & 0xc0000u) >> 18u;
-    let dir_index = (vertex & 0x700000u) >> 20u;
+    let dir_index = (vertex & 0x1c0000u) >> 18u;
+    out.texture_layer = (vertex & 0x1fe00000u) >> 21u;
+    let ao = (vertex & 0x60000000u) >> 29u;
 
-    out.uvs = uvs[uv_index];
-    out.light_level = light_levels[dir_index];
+    out.uvs = vec2<f32>(dot(position, u_axes[dir_index]), dot(position, v_axes[dir_index]));
+    out.light_level = light_levels[dir_index] * ao_levels[ao] * light_brightness(light);
     return out;
 }
 
 @group(1) @binding(0)
-var diffuse_texture: texture_2d<f32>;
+var diffuse_texture: texture_2d_array<f32>;
 @group(1) @binding(1)
 var diffuse_sampler: sampler;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    var color = textureSample(diffuse_texture, diffuse_sampler, in.uvs) * in.light_level;
+    var color = textureSample(diffuse_texture, diffuse_sampler, in.uvs, i32(in.texture_layer)) * in.light_level;
     color.a = 1.0;
     return color;
 }
@@ -0,0 +1,268 @@
+use std::sync::{Arc, Condvar, Mutex};
+
+use bevy_ecs::prelude::*;
+use crossbeam_channel::{Receiver, Sender};
+use opencuboids_common::{
+    block_to_chunk_pos, in_bounds, iter_3d, iter_3d_vec, BlockID, Chunk, Light, AIR, CHUNK_SIZE,
+};
+
+use super::chunk_renderer::{mesh_chunk, MeshBlocks, Vertex};
+use crate::world::ChunkManager;
+
+/// Copy of a chunk and the blocks and light touching it in all 26 neighbouring chunks, everything needed
+/// to mesh the chunk on another thread
+pub struct ChunkSnapshot {
+    chunk: Chunk,
+    /// Uses the index of the neighbour offset then the position along the axes the offset is
+    /// zero on, the faces have a layer of blocks, edges a row and corners a single block
+    borders: [Vec<(BlockID, Light)>; 27],
+}
+
+/// Gets the index of the border of the neighbour at the offset from the chunk
+fn border_index(offset: glam::IVec3) -> usize {
+    ((offset.x + 1) * 9 + (offset.y + 1) * 3 + (offset.z + 1)) as usize
+}
+
+impl ChunkSnapshot {
+    pub fn new(chunk_manager: &ChunkManager, chunk_pos: glam::IVec3) -> Self {
+        let size = CHUNK_SIZE as i32;
+        let mut borders = std::array::from_fn(|_| Vec::new());
+        for offset in iter_3d(-1, 2).filter(|offset| *offset != glam::IVec3::ZERO) {
+            let neighbour = &chunk_manager.chunk_map[&(chunk_pos + offset)];
+
+            // The blocks on the side of the neighbour touching the chunk
+            let mut start = glam::IVec3::ZERO;
+            let mut end = glam::IVec3::splat(size);
+            for axis in 0..3 {
+                match offset[axis] {
+                    -1 => start[axis] = size - 1,
+                    1 => end[axis] = 1,
+                    _ => (),
+                }
+            }
+            borders[border_index(offset)] = iter_3d_vec(start, end)
+                .map(|pos| {
+                    let pos = pos.as_uvec3();
+                    (neighbour.get_block(pos), neighbour.get_light(pos))
+                })
+                .collect();
+        }
+
+        Self {
+            chunk: chunk_manager.chunk_map[&chunk_pos].clone(),
+            borders,
+        }
+    }
+
+    /// Gets a block and its light touching the chunk using a position relative to the chunk
+    fn get_outside_block(&self, pos: glam::IVec3) -> (BlockID, Light) {
+        let offset = block_to_chunk_pos(pos);
+        assert!(
+            offset != glam::IVec3::ZERO && offset.abs().max_element() == 1,
+            "Position isn't in a neighbour of the chunk"
+        );
+
+        // Borders are stored in the same order as iter_3d_vec with a length of 1 along the axes
+        // that are outside of the chunk
+        let index = (0..3)
+            .filter(|axis| offset[*axis] == 0)
+            .fold(0, |index, axis| index * CHUNK_SIZE + pos[axis] as usize);
+        self.borders[border_index(offset)][index]
+    }
+
+    fn mesh(&self, blocks: &MeshBlocks) -> Vec<Vertex> {
+        // Nothing to mesh in a chunk of only air
+        if self.chunk.single_block() == Some(AIR) {
+            return Vec::new();
+        }
+
+        mesh_chunk(&self.chunk, blocks, |pos| self.get_outside_block(pos))
+    }
+}
+
+struct Job {
+    chunk_pos: glam::IVec3,
+    version: u64,
+    snapshot: ChunkSnapshot,
+    blocks: Arc<MeshBlocks>,
+}
+
+pub struct MeshResult {
+    pub chunk_pos: glam::IVec3,
+    version: u64,
+    pub vertices: Vec<Vertex>,
+}
+
+#[derive(Default)]
+struct JobQueue {
+    jobs: Vec<Job>,
+    /// Jobs closest to this chunk get done first
+    center: glam::IVec3,
+}
+
+/// Meshes chunks on a pool of worker threads, closest chunks to the player first
+#[derive(Resource)]
+pub struct ChunkMesher {
+    queue: Arc<(Mutex<JobQueue>, Condvar)>,
+    receiver: Receiver<MeshResult>,
+    /// The version of the latest job of each chunk being meshed, older results get thrown away
+    versions: bevy_utils::HashMap<glam::IVec3, u64>,
+    next_version: u64,
+    blocks: Option<Arc<MeshBlocks>>,
+}
+
+impl Default for ChunkMesher {
+    fn default() -> Self {
+        let queue = Arc::<(Mutex<JobQueue>, Condvar)>::default();
+        let (sender, receiver) = crossbeam_channel::unbounded();
+
+        // Leave a thread for rendering
+        let thread_count = std::thread::available_parallelism().map_or(1, |count| count.get());
+        for _ in 0..thread_count.saturating_sub(1).max(1) {
+            let queue = queue.clone();
+            let sender = sender.clone();
+            std::thread::spawn(move || worker(&queue, sender));
+        }
+
+        Self {
+            queue,
+            receiver,
+            versions: Default::default(),
+            next_version: 0,
+            blocks: None,
+        }
+    }
+}
+
+impl ChunkMesher {
+    pub fn has_blocks(&self) -> bool {
+        self.blocks.is_some()
+    }
+
+    /// Sets the blocks used by chunks queued after this
+    pub fn set_blocks(&mut self, blocks: MeshBlocks) {
+        self.blocks = Some(Arc::new(blocks));
+    }
+
+    pub fn queue(&mut self, chunk_pos: glam::IVec3, snapshot: ChunkSnapshot) {
+        let blocks = self
+            .blocks
+            .clone()
+            .expect("Blocks have to be set before meshing");
+        let version = self.next_version;
+        self.next_version += 1;
+        self.versions.insert(chunk_pos, version);
+
+        let (queue, condvar) = &*self.queue;
+        let mut queue = queue.lock().unwrap();
+        // A job for an older version of the chunk doesn't need to be done anymore
+        queue.jobs.retain(|job| job.chunk_pos != chunk_pos);
+        queue.jobs.push(Job {
+            chunk_pos,
+            version,
+            snapshot,
+            blocks,
+        });
+        condvar.notify_one();
+    }
+
+    /// Prioritises chunks closest to the center and cancels the chunks too far away from it
+    pub fn set_center(&mut self, center: glam::IVec3, distance: i32) {
+        let mut queue = self.queue.0.lock().unwrap();
+        if queue.center == center {
+            return;
+        }
+
+        queue.center = center;
+        queue
+            .jobs
+            .retain(|job| in_bounds(job.chunk_pos, center, distance));
+        self.versions
+            .retain(|chunk_pos, _| in_bounds(*chunk_pos, center, distance));
+    }
+
+    /// Gets the meshes that have finished since last time
+    pub fn finished(&mut self) -> Vec<MeshResult> {
+        self.receiver
+            .try_iter()
+            .filter(|result| {
+                if self.versions.get(&result.chunk_pos) == Some(&result.version) {
+                    self.versions.remove(&result.chunk_pos);
+                    true
+                } else {
+                    false
+                }
+            })
+            .collect()
+    }
+}
+
+fn worker(queue: &(Mutex<JobQueue>, Condvar), sender: Sender<MeshResult>) {
+    let (queue, condvar) = queue;
+    loop {
+        let job = {
+            let mut queue = condvar
+                .wait_while(queue.lock().unwrap(), |queue| queue.jobs.is_empty())
+                .unwrap();
+
+            let center = queue.center;
+            let (closest, _) = queue
+                .jobs
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, job)| {
+                    let offset = job.chunk_pos - center;
+                    offset.dot(offset)
+                })
+                .unwrap();
+            queue.jobs.swap_remove(closest)
+        };
+
+        let result = MeshResult {
+            chunk_pos: job.chunk_pos,
+            version: job.version,
+            vertices: job.snapshot.mesh(&job.blocks),
+        };
+
+        // The mesher has been dropped
+        if sender.send(result).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opencuboids_common::block_to_local_pos;
+
+    #[test]
+    fn snapshot_borders() {
+        let mut chunk_manager = ChunkManager::default();
+        for chunk_pos in iter_3d(-1, 2) {
+            let mut chunk = Chunk::new(chunk_pos);
+            for pos in iter_3d(0, CHUNK_SIZE as i32) {
+                let block_pos = chunk_pos * CHUNK_SIZE as i32 + pos;
+                let id = (block_pos.x * 7 + block_pos.y * 3 + block_pos.z).rem_euclid(50);
+                chunk.set_block(pos.as_uvec3(), id as BlockID);
+                chunk.set_light(pos.as_uvec3(), Light::new(id as u8 % 16, pos.y as u8 % 16));
+            }
+            chunk_manager.chunk_map.insert(chunk_pos, chunk);
+        }
+
+        let snapshot = ChunkSnapshot::new(&chunk_manager, glam::IVec3::ZERO);
+        for pos in iter_3d(-1, CHUNK_SIZE as i32 + 1) {
+            // Every position touching the chunk including on the edges and corners
+            let outside =
+                pos.cmplt(glam::IVec3::ZERO) | pos.cmpge(glam::IVec3::splat(CHUNK_SIZE as i32));
+            if outside.any() {
+                let chunk = &chunk_manager.chunk_map[&block_to_chunk_pos(pos)];
+                let local_pos = block_to_local_pos(pos);
+                assert_eq!(
+                    snapshot.get_outside_block(pos),
+                    (chunk.get_block(local_pos), chunk.get_light(local_pos))
+                );
+            }
+        }
+    }
+}
@@ -1,39 +1,82 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
 use bevy_ecs::prelude::*;
 use opencuboids_common::{
-    in_bounds, iter_3d, Chunk, CHUNK_SIZE, CHUNK_VOLUME, DIRECTION_TO_VECTOR,
+    in_bounds, BlockID, BlockRegistry, Chunk, Light, CHUNK_SIZE, CHUNK_VOLUME, DIRECTION_TO_VECTOR,
 };
 
-use crate::world::{ChunkManager, WorldTransform, RENDER_DISTANCE};
+use crate::{
+    camera::Camera,
+    world::{Blocks, ChunkManager, WorldTransform, RENDER_DISTANCE},
+};
 
 use super::{
     bind_group::{BindGroup, BindGroupEntry},
     buffer::{new_buffer_quad_index, Buffer},
+    chunk_mesher::{ChunkMesher, ChunkSnapshot},
+    frustum::Frustum,
     render_pipeline::RenderPipeline,
     texture::Texture,
     MainRenderer, RenderState,
 };
 
-// Worst case scenario of chunk: 3D chessboard pattern
+/// Worst case scenario of chunk: 3D chessboard pattern, every mesh shares an index buffer
+/// big enough for this many quads
 const MAX_QUADS: usize = CHUNK_VOLUME / 2 * 6;
 
-/// Vertex is a packed 32-bit unsigned int containing all the vertex data
-///    x      y      z    uv dir
-/// |‾‾‾‾‾||‾‾‾‾‾| |‾‾‾‾‾||‾||‾|
+/// Directory the block textures are loaded from relative to the executable or the workspace
+/// when running through cargo, textures are named after their file
+const TEXTURE_DIR: &str = "assets/textures";
+/// Directory in the texture directory with the textures that aren't for blocks
+pub const ENTITY_TEXTURE_DIR: &str = "entities";
+/// Texture array layer used for faces with textures that couldn't be found
+pub const MISSING_TEXTURE_LAYER: u32 = 0;
+/// The vertex only has 8 bits for the layer, including the missing texture
+const MAX_TEXTURE_LAYERS: usize = 256;
+
+/// Vertex is two packed 32-bit unsigned ints, texture coordinates come from the position so they
+/// tile across merged faces
+///
+/// The first has the position, direction, texture layer and ambient occlusion:
+///    x      y      z    dir   layer   ao
+/// |‾‾‾‾‾||‾‾‾‾‾| |‾‾‾‾‾||‾‾| |‾‾‾‾‾‾‾‾||
 /// 0000 0000 0000 0000 0000 0000 0000 0000  
+///
+/// The second has the sky light and block light times 4 so they can keep the fraction from
+/// being averaged:
+///   sky   block
+/// |‾‾‾‾‾||‾‾‾‾‾|
+/// 0000 0000 0000
 #[repr(C)]
-#[derive(Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct Vertex(u32);
+#[derive(Default, Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vertex(u32, u32);
 
 impl Vertex {
     const LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
         array_stride: std::mem::size_of::<Vertex>() as u64,
         step_mode: wgpu::VertexStepMode::Vertex,
-        attributes: &wgpu::vertex_attr_array![0 => Uint32],
+        attributes: &wgpu::vertex_attr_array![0 => Uint32, 1 => Uint32],
     };
+
+    fn new(pos: glam::UVec3, dir_index: usize, texture_layer: u32, ao: u32, light: u32) -> Self {
+        debug_assert!((texture_layer as usize) < MAX_TEXTURE_LAYERS);
+        Self(
+            pos.x
+                | pos.y << 6
+                | pos.z << 12
+                | (dir_index as u32) << 18
+                | texture_layer << 21
+                | ao << 29,
+            light,
+        )
+    }
 }
 
 /// Uses a direction index then vertex index to index CUBE_VERTICES
-const CUBE_INDICES: &[usize] = &[
+pub const CUBE_INDICES: &[usize] = &[
     6, 7, 4, 5, // North
     3, 2, 1, 0, // South
     2, 6, 5, 1, // East
@@ -42,7 +85,7 @@ const CUBE_INDICES: &[usize] = &[
     0, 1, 5, 4, // Bottom
 ];
 
-const CUBE_VERTICES: &[glam::UVec3] = &[
+pub const CUBE_VERTICES: &[glam::UVec3] = &[
     // Top face
     glam::uvec3(0, 0, 0),
     glam::uvec3(1, 0, 0),
@@ -55,79 +98,357 @@ const CUBE_VERTICES: &[glam::UVec3] = &[
     glam::uvec3(0, 1, 1),
 ];
 
-fn add_face(
-    verticies: &mut [Vertex],
-    vertex_i: &mut usize,
+/// Uses a direction index to get the axis the faces point along then the two axes across them
+pub const DIRECTION_AXES: &[(usize, usize, usize)] = &[
+    (2, 0, 1), // North
+    (2, 0, 1), // South
+    (0, 2, 1), // East
+    (0, 2, 1), // West
+    (1, 0, 2), // Top
+    (1, 0, 2), // Bottom
+];
+
+/// Adds the face of a box in the direction, size is the amount of blocks the box covers
+fn add_quad(
+    vertices: &mut Vec<Vertex>,
     dir_index: usize,
-    block_pos: glam::UVec3,
+    pos: glam::UVec3,
+    size: glam::UVec3,
+    face: Face,
+    texture_layer: u32,
 ) {
-    // Add face
-    for i in 0..4 {
-        let pos = CUBE_VERTICES[CUBE_INDICES[(dir_index * 4) + i]] + block_pos;
+    // Quads get split into triangles along the diagonal from the first to the third vertex,
+    // starting from the second vertex splits it along the other diagonal instead so the darker
+    // diagonal is always the one split along and the occlusion looks the same in every direction
+    let ao = face.ao;
+    let start = usize::from(ao[0] + ao[2] > ao[1] + ao[3]);
+    for i in (start..start + 4).map(|i| i % 4) {
+        let corner = CUBE_VERTICES[CUBE_INDICES[(dir_index * 4) + i]];
+        vertices.push(Vertex::new(
+            pos + corner * size,
+            dir_index,
+            texture_layer,
+            ao[i] as u32,
+            face.light[i],
+        ));
+    }
+}
 
-        // Pack vertex data
-        let vertex = pos.x | pos.y << 6 | pos.z << 12 | (i as u32) << 18 | (dir_index as u32) << 20;
-        verticies[*vertex_i] = Vertex(vertex);
-        *vertex_i += 1;
+/// Loads every PNG in the directory into images for a texture array, returns the images and the
+/// layer of each texture name
+pub fn load_textures(
+    texture_dir: &Path,
+) -> (Vec<image::DynamicImage>, bevy_utils::HashMap<String, u32>) {
+    let mut paths = match std::fs::read_dir(texture_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            log::error!(
+                "Failed to read textures from {} - {}",
+                texture_dir.display(),
+                err
+            );
+            Vec::new()
+        }
+    };
+    paths.sort();
+
+    let mut images = Vec::new();
+    let mut layers = bevy_utils::HashMap::default();
+    for path in paths {
+        let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+            continue;
+        };
+
+        if images.len() + 1 >= MAX_TEXTURE_LAYERS {
+            log::error!(
+                "Only {} textures are supported, {} won't be loaded",
+                MAX_TEXTURE_LAYERS - 1,
+                path.display()
+            );
+            continue;
+        }
+
+        match image::open(&path) {
+            Ok(image) => {
+                layers.insert(name.to_owned(), images.len() as u32 + 1);
+                images.push(image);
+            }
+            Err(err) => log::error!("Failed to load texture {} - {}", path.display(), err),
+        }
     }
+
+    // Magenta and black checkerboard the same size as the other textures
+    let (width, height) = images
+        .first()
+        .map_or((16, 16), |image| (image.width(), image.height()));
+    let missing = image::RgbaImage::from_fn(width, height, |x, y| {
+        if (x * 2 / width + y * 2 / height) % 2 == 0 {
+            image::Rgba([255, 0, 255, 255])
+        } else {
+            image::Rgba([0, 0, 0, 255])
+        }
+    });
+    images.insert(MISSING_TEXTURE_LAYER as usize, missing.into());
+
+    (images, layers)
 }
 
-#[derive(Component)]
-pub struct ChunkMesh {
-    vertex_buffer: Buffer<Vertex>,
-    pub chunk_pos: glam::IVec3,
+/// Uses the textures next to the executable if there are any, otherwise the ones in the workspace
+pub fn texture_dir() -> PathBuf {
+    let exe_dir = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join(TEXTURE_DIR)));
+    match exe_dir {
+        Some(dir) if dir.is_dir() => dir,
+        _ => Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("..")
+            .join(TEXTURE_DIR),
+    }
 }
 
-impl ChunkMesh {
-    pub fn new(device: &wgpu::Device, chunk: &Chunk, chunk_manager: &ChunkManager) -> Option<Self> {
-        let mut verticies = [Vertex::default(); MAX_QUADS * 4];
-        let mut vertex_i = 0;
+/// What the mesher needs to know about blocks, shared with the mesher threads
+pub struct MeshBlocks {
+    pub registry: Arc<BlockRegistry>,
+    /// Uses a block id then direction index to get the texture layer of a face
+    face_layers: Vec<[u32; 6]>,
+}
+
+impl MeshBlocks {
+    pub fn new(
+        registry: Arc<BlockRegistry>,
+        texture_layers: &bevy_utils::HashMap<String, u32>,
+    ) -> Self {
+        let max_id = registry.iter().map(|block| block.id).max().unwrap_or(0);
+        let mut face_layers = vec![[MISSING_TEXTURE_LAYER; 6]; max_id as usize + 1];
 
-        let chunk_block_pos = chunk.pos * CHUNK_SIZE as i32;
-        for block_pos in iter_3d(0, CHUNK_SIZE as i32) {
-            if chunk.get_block(block_pos.as_uvec3()) == 0 {
+        for block in registry.iter() {
+            let Some(textures) = &block.textures else {
                 continue;
+            };
+
+            for (dir_index, layer) in face_layers[block.id as usize].iter_mut().enumerate() {
+                let name = textures.get(dir_index);
+                match texture_layers.get(name) {
+                    Some(texture_layer) => *layer = *texture_layer,
+                    None => log::warn!("Missing texture {} of block {}", name, block.name),
+                }
             }
+        }
 
-            for dir_index in 0..6 {
-                let dir_vec = DIRECTION_TO_VECTOR[dir_index];
-                let neighbour_pos = block_pos + dir_vec;
-                // Only get chunk via chunk_manager if on edge because map lookup slow
-                if chunk
-                    .try_get_block(neighbour_pos.as_uvec3())
-                    .unwrap_or_else(|| chunk_manager.get_block(neighbour_pos + chunk_block_pos))
-                    == 0
-                {
-                    add_face(
-                        &mut verticies,
-                        &mut vertex_i,
+        Self {
+            registry,
+            face_layers,
+        }
+    }
+
+    fn face_layer(&self, id: BlockID, dir_index: usize) -> u32 {
+        self.face_layers
+            .get(id as usize)
+            .map_or(MISSING_TEXTURE_LAYER, |layers| layers[dir_index])
+    }
+}
+
+/// Faces can only be merged if everything that changes how they look is the same
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Face {
+    id: BlockID,
+    /// Ambient occlusion of each vertex of the face from 0 (darkest) to 3 (no occlusion)
+    ao: [u8; 4],
+    /// Packed smooth light of each vertex of the face like in Vertex
+    light: [u32; 4],
+}
+
+/// Gets how much a vertex is occluded by the blocks touching it in front of the face, a corner
+/// between two sides is fully dark even without the corner block
+fn vertex_ao(side_a: bool, side_b: bool, corner: bool) -> u8 {
+    if side_a && side_b {
+        0
+    } else {
+        3 - (side_a as u8 + side_b as u8 + corner as u8)
+    }
+}
+
+/// Gets the light of a vertex by averaging the light of the blocks around it that light can get
+/// through, packed like in Vertex
+fn vertex_light(lights: [Option<Light>; 4]) -> u32 {
+    let (count, sky, block) =
+        lights
+            .iter()
+            .flatten()
+            .fold((0, 0, 0), |(count, sky, block), light| {
+                (
+                    count + 1,
+                    sky + light.sky() as u32,
+                    block + light.block() as u32,
+                )
+            });
+    (sky * 4 / count) | (block * 4 / count) << 6
+}
+
+/// Gets the face of the block facing the direction if it can be seen
+fn visible_face(
+    chunk: &Chunk,
+    blocks: &BlockRegistry,
+    get_outside_block: &impl Fn(glam::IVec3) -> (BlockID, Light),
+    block_pos: glam::IVec3,
+    dir_index: usize,
+) -> Option<Face> {
+    let id = chunk.get_block(block_pos.as_uvec3());
+    blocks.get(id).textures.as_ref()?;
+
+    // Only look outside of the chunk if on edge because that's slower
+    let get_block = |pos: glam::IVec3| {
+        let local_pos = pos.as_uvec3();
+        chunk
+            .try_get_block(local_pos)
+            .map(|id| (id, chunk.get_light(local_pos)))
+            .unwrap_or_else(|| get_outside_block(pos))
+    };
+    let neighbour_pos = block_pos + DIRECTION_TO_VECTOR[dir_index];
+    let (neighbour_id, neighbour_light) = get_block(neighbour_pos);
+
+    // Faces between two of the same transparent block are hidden
+    if neighbour_id == id || !blocks.is_transparent(neighbour_id) {
+        return None;
+    }
+
+    // Each vertex is occluded and lit by the blocks around its corner in the layer in front of
+    // the face
+    let (_, u_axis, v_axis) = DIRECTION_AXES[dir_index];
+    let mut face = Face {
+        id,
+        ao: [0; 4],
+        light: [0; 4],
+    };
+    for i in 0..4 {
+        let corner = CUBE_VERTICES[CUBE_INDICES[(dir_index * 4) + i]].as_ivec3();
+        let mut u_offset = glam::IVec3::ZERO;
+        u_offset[u_axis] = corner[u_axis] * 2 - 1;
+        let mut v_offset = glam::IVec3::ZERO;
+        v_offset[v_axis] = corner[v_axis] * 2 - 1;
+
+        let (side_a_id, side_a_light) = get_block(neighbour_pos + u_offset);
+        let (side_b_id, side_b_light) = get_block(neighbour_pos + v_offset);
+        let (corner_id, corner_light) = get_block(neighbour_pos + u_offset + v_offset);
+        let side_a = !blocks.is_transparent(side_a_id);
+        let side_b = !blocks.is_transparent(side_b_id);
+        let corner = !blocks.is_transparent(corner_id);
+        face.ao[i] = vertex_ao(side_a, side_b, corner);
+
+        // Light can't get to the corner block past both sides
+        face.light[i] = vertex_light([
+            Some(neighbour_light),
+            (!side_a).then_some(side_a_light),
+            (!side_b).then_some(side_b_light),
+            (!(corner || side_a && side_b)).then_some(corner_light),
+        ]);
+    }
+
+    Some(face)
+}
+
+/// Creates the vertices of every visible face in the chunk, merging faces next to each other
+/// that look the same into bigger quads
+///
+/// get_outside_block gets the blocks and their light around the chunk using a position relative
+/// to the chunk, including the ones diagonal to it for ambient occlusion and smooth lighting.
+pub fn mesh_chunk(
+    chunk: &Chunk,
+    blocks: &MeshBlocks,
+    get_outside_block: impl Fn(glam::IVec3) -> (BlockID, Light),
+) -> Vec<Vertex> {
+    const SIZE: usize = CHUNK_SIZE;
+    let mut vertices = Vec::new();
+    let mut faces = [None; SIZE * SIZE];
+
+    for (dir_index, &(normal_axis, u_axis, v_axis)) in DIRECTION_AXES.iter().enumerate() {
+        for layer in 0..SIZE {
+            // Get the faces in the layer facing the direction
+            for v in 0..SIZE {
+                for u in 0..SIZE {
+                    let mut block_pos = glam::IVec3::ZERO;
+                    block_pos[normal_axis] = layer as i32;
+                    block_pos[u_axis] = u as i32;
+                    block_pos[v_axis] = v as i32;
+                    faces[v * SIZE + u] = visible_face(
+                        chunk,
+                        &blocks.registry,
+                        &get_outside_block,
+                        block_pos,
                         dir_index,
-                        block_pos.as_uvec3(),
                     );
                 }
             }
+
+            // Grow each face as wide as possible then as tall as possible
+            for v in 0..SIZE {
+                let mut u = 0;
+                while u < SIZE {
+                    let Some(face) = faces[v * SIZE + u] else {
+                        u += 1;
+                        continue;
+                    };
+
+                    let width = faces[v * SIZE + u..(v + 1) * SIZE]
+                        .iter()
+                        .take_while(|other| **other == Some(face))
+                        .count();
+                    let height = (v..SIZE)
+                        .take_while(|row| {
+                            faces[row * SIZE + u..row * SIZE + u + width]
+                                .iter()
+                                .all(|other| *other == Some(face))
+                        })
+                        .count();
+
+                    for row in v..v + height {
+                        faces[row * SIZE + u..row * SIZE + u + width].fill(None);
+                    }
+
+                    let mut pos = glam::UVec3::ZERO;
+                    pos[normal_axis] = layer as u32;
+                    pos[u_axis] = u as u32;
+                    pos[v_axis] = v as u32;
+                    let mut size = glam::UVec3::ONE;
+                    size[u_axis] = width as u32;
+                    size[v_axis] = height as u32;
+                    let texture_layer = blocks.face_layer(face.id, dir_index);
+                    add_quad(&mut vertices, dir_index, pos, size, face, texture_layer);
+
+                    u += width;
+                }
+            }
         }
+    }
 
-        if vertex_i > 0 {
-            Some(Self {
-                vertex_buffer: Buffer::new(
-                    &device,
-                    wgpu::BufferUsages::VERTEX,
-                    bytemuck::cast_slice(&verticies[0..vertex_i + 1]),
-                ),
-                chunk_pos: chunk.pos,
-            })
-        } else {
-            None
+    vertices
+}
+
+#[derive(Component)]
+pub struct ChunkMesh {
+    vertex_buffer: Buffer<Vertex>,
+    pub chunk_pos: glam::IVec3,
+}
+
+impl ChunkMesh {
+    pub fn new(device: &wgpu::Device, chunk_pos: glam::IVec3, vertices: &[Vertex]) -> Self {
+        Self {
+            vertex_buffer: Buffer::new(device, wgpu::BufferUsages::VERTEX, vertices),
+            chunk_pos,
         }
     }
 }
 
 #[derive(Resource)]
 pub struct ChunkRenderer {
-    index_buffer: Buffer<u16>,
+    index_buffer: Buffer<u32>,
     render_pipeline: RenderPipeline,
-    texture_bind_group: BindGroup,
+    pub texture_bind_group: BindGroup,
+    /// The texture array layer of each texture name
+    pub texture_layers: bevy_utils::HashMap<String, u32>,
 }
 
 impl FromWorld for ChunkRenderer {
@@ -135,33 +456,14 @@ impl FromWorld for ChunkRenderer {
         let renderer = world.resource::<RenderState>();
         let device = &renderer.device;
 
-        let diffuse_image = image::load_from_memory(include_bytes!("dirt.png")).unwrap();
-        let diffuse_texture = Texture::new(device, &renderer.queue, &diffuse_image);
+        let (images, texture_layers) = load_textures(&texture_dir());
+        let diffuse_texture = Texture::new(device, &renderer.queue, &images);
+        let texture_bind_group = new_texture_bind_group(device, &diffuse_texture);
 
-        let texture_bind_group = BindGroup::new(
-            device,
-            &[
-                BindGroupEntry {
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                BindGroupEntry {
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-        );
-
-        let index_buffer = new_buffer_quad_index(&device, MAX_QUADS);
+        let index_buffer = new_buffer_quad_index(device, MAX_QUADS);
 
         let render_pipeline = RenderPipeline::new(
-            &device,
+            device,
             wgpu::include_wgsl!("chunk.wgsl"),
             &[
                 &renderer.global_bind_group.layout,
@@ -180,55 +482,104 @@ impl FromWorld for ChunkRenderer {
             render_pipeline,
             index_buffer,
             texture_bind_group,
+            texture_layers,
         }
     }
 }
 
+/// Binds a texture array and its sampler for the fragment shader
+pub fn new_texture_bind_group(device: &wgpu::Device, texture: &Texture) -> BindGroup {
+    BindGroup::new(
+        device,
+        &[
+            BindGroupEntry {
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            BindGroupEntry {
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            },
+        ],
+    )
+}
+
 pub fn chunk_mesh_gen(
     mut commands: Commands,
     renderer: Res<RenderState>,
     mut chunk_manager: ResMut<ChunkManager>,
+    mut mesher: ResMut<ChunkMesher>,
+    chunk_renderer: Res<ChunkRenderer>,
+    blocks: Option<Res<Blocks>>,
     mut query: Query<(Entity, &mut ChunkMesh)>,
 ) {
-    if chunk_manager.chunks_left_loading != 0 {
+    let (Some(blocks), Some(center)) = (blocks, chunk_manager.chunk_pos_center) else {
         return;
+    };
+
+    if blocks.is_changed() || !mesher.has_blocks() {
+        mesher.set_blocks(MeshBlocks::new(
+            blocks.0.clone(),
+            &chunk_renderer.texture_layers,
+        ));
     }
 
-    for _ in 0..4 {
-        if let Some(chunk_pos) = chunk_manager.chunk_update_queue.pop_front() {
-            let chunk = chunk_manager.chunk_map.get(&chunk_pos).unwrap();
-            let block_pos = chunk_pos.as_vec3() * CHUNK_SIZE as f32;
-            let mesh = ChunkMesh::new(&renderer.device, &chunk, &chunk_manager);
-            if let Some(mesh) = mesh {
-                commands.spawn((
-                    WorldTransform {
-                        position: block_pos,
-                        ..Default::default()
-                    },
-                    mesh,
-                ));
-            }
+    mesher.set_center(center, RENDER_DISTANCE);
+    while let Some(chunk_pos) = chunk_manager.chunk_update_queue.pop_front() {
+        let snapshot = ChunkSnapshot::new(&chunk_manager, chunk_pos);
+        mesher.queue(chunk_pos, snapshot);
+    }
+
+    let mut meshed_chunks = Vec::new();
+    for result in mesher.finished() {
+        meshed_chunks.push(result.chunk_pos);
+        if !result.vertices.is_empty() {
+            let block_pos = result.chunk_pos.as_vec3() * CHUNK_SIZE as f32;
+            commands.spawn((
+                WorldTransform {
+                    position: block_pos,
+                    ..Default::default()
+                },
+                ChunkMesh::new(&renderer.device, result.chunk_pos, &result.vertices),
+            ));
         }
     }
 
-    // Remove any chunk meshes outside render distance
+    // Remove any chunk meshes outside render distance or that have been replaced
     for (entity, mesh) in query.iter_mut() {
-        if !in_bounds(
-            mesh.chunk_pos,
-            chunk_manager.chunk_pos_center.unwrap(),
-            RENDER_DISTANCE,
-        ) {
+        if !in_bounds(mesh.chunk_pos, center, RENDER_DISTANCE)
+            || meshed_chunks.contains(&mesh.chunk_pos)
+        {
             commands.entity(entity).despawn();
         }
     }
 }
 
+/// How many chunk meshes were drawn and skipped for being outside of the camera last frame
+#[derive(Default, Resource, PartialEq, Eq)]
+pub struct ChunkRenderStats {
+    pub drawn: usize,
+    pub culled: usize,
+}
+
 pub fn chunk_render(
     render_state: ResMut<RenderState>,
     mut renderer: ResMut<MainRenderer>,
     chunk_renderer: Res<ChunkRenderer>,
+    mut stats: ResMut<ChunkRenderStats>,
+    camera_query: Query<(&Camera, &WorldTransform)>,
     query: Query<(&WorldTransform, &ChunkMesh)>,
 ) {
+    let (camera, camera_transform) = camera_query.single();
+    let frustum = Frustum::from_view_projection(camera.view_projection(*camera_transform));
+    let mut new_stats = ChunkRenderStats::default();
+
     let mut render_pass = renderer.begin_render_pass(Some(&render_state.depth_texture.view));
 
     render_pass.set_pipeline(&chunk_renderer.render_pipeline.pipeline);
@@ -237,10 +588,17 @@ pub fn chunk_render(
 
     render_pass.set_index_buffer(
         chunk_renderer.index_buffer.buf.slice(..),
-        wgpu::IndexFormat::Uint16,
+        wgpu::IndexFormat::Uint32,
     );
 
     for (transform, mesh) in query.iter() {
+        let chunk_end = transform.position + CHUNK_SIZE as f32;
+        if !frustum.intersects_aabb(transform.position, chunk_end) {
+            new_stats.culled += 1;
+            continue;
+        }
+
+        new_stats.drawn += 1;
         let index_count = mesh.vertex_buffer.len / 4 * 6;
         render_pass.set_vertex_buffer(0, mesh.vertex_buffer.buf.slice(..));
         render_pass.set_push_constants(
@@ -251,4 +609,251 @@ pub fn chunk_render(
 
         render_pass.draw_indexed(0..index_count as u32, 0, 0..1);
     }
+
+    if *stats != new_stats {
+        log::debug!(
+            "Drawing {} chunks, culled {}",
+            new_stats.drawn,
+            new_stats.culled
+        );
+        *stats = new_stats;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::buffer::quad_indices;
+    use opencuboids_common::{iter_3d, AIR};
+
+    /// Smooth hills with long slopes that can be merged, world generation is too rough for this
+    fn terrain_height(pos: glam::IVec3) -> i32 {
+        let (x, z) = (pos.x as f32, pos.z as f32);
+        16 + ((x / 10.0).sin() * (z / 10.0).cos() * 10.0) as i32
+    }
+
+    fn terrain_block(blocks: &BlockRegistry, pos: glam::IVec3) -> BlockID {
+        let height = terrain_height(pos);
+        let name = match pos.y {
+            y if y > height => "air",
+            y if y == height => "grass",
+            y if y > height - 3 => "dirt",
+            _ => "stone",
+        };
+        blocks.id(name).unwrap()
+    }
+
+    fn mesh_blocks() -> MeshBlocks {
+        let texture_layers = ["dirt", "grass_side", "grass_top", "stone"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.to_string(), i as u32 + 1))
+            .collect();
+        MeshBlocks::new(Arc::new(BlockRegistry::default()), &texture_layers)
+    }
+
+    fn quad_area(quad: &[Vertex]) -> u32 {
+        let unpack = |vertex: Vertex| {
+            glam::ivec3(
+                (vertex.0 & 0x3f) as i32,
+                (vertex.0 >> 6 & 0x3f) as i32,
+                (vertex.0 >> 12 & 0x3f) as i32,
+            )
+        };
+        let side_a = (unpack(quad[1]) - unpack(quad[0])).abs();
+        let side_b = (unpack(quad[3]) - unpack(quad[0])).abs();
+        (side_a.max_element() * side_b.max_element()) as u32
+    }
+
+    #[test]
+    fn greedy_meshing() {
+        let blocks = mesh_blocks();
+        let mut chunk = Chunk::new(glam::IVec3::ZERO);
+        for pos in iter_3d(0, CHUNK_SIZE as i32) {
+            chunk.set_block(pos.as_uvec3(), terrain_block(&blocks.registry, pos));
+        }
+        let get_outside_block = |pos| (terrain_block(&blocks.registry, pos), Light::SKY);
+
+        // One quad for every visible block face
+        let mut naive_quads = 0;
+        for pos in iter_3d(0, CHUNK_SIZE as i32) {
+            for dir_index in 0..6 {
+                if visible_face(&chunk, &blocks.registry, &get_outside_block, pos, dir_index)
+                    .is_some()
+                {
+                    naive_quads += 1;
+                }
+            }
+        }
+
+        let vertices = mesh_chunk(&chunk, &blocks, get_outside_block);
+        let quads = vertices.chunks(4).collect::<Vec<_>>();
+        let area = quads.iter().map(|quad| quad_area(quad)).sum::<u32>();
+
+        // Every face gets covered exactly once by fewer quads, faces with different ambient
+        // occlusion on the slopes can't be merged
+        assert_eq!(area, naive_quads);
+        assert!(quads.len() * 3 < naive_quads as usize * 2);
+
+        // Merged quads of a flat floor
+        let mut chunk = Chunk::new(glam::IVec3::ZERO);
+        for pos in iter_3d(0, CHUNK_SIZE as i32).filter(|pos| pos.y == 0) {
+            chunk.set_block(pos.as_uvec3(), blocks.registry.id("stone").unwrap());
+        }
+        let vertices = mesh_chunk(&chunk, &blocks, |_| (AIR, Light::default()));
+        assert_eq!(vertices.len(), 6 * 4);
+    }
+
+    #[test]
+    fn checkerboard_indices() {
+        let blocks = mesh_blocks();
+        let stone = blocks.registry.id("stone").unwrap();
+        let mut chunk = Chunk::new(glam::IVec3::ZERO);
+        for pos in iter_3d(0, CHUNK_SIZE as i32) {
+            if (pos.x + pos.y + pos.z) % 2 == 0 {
+                chunk.set_block(pos.as_uvec3(), stone);
+            }
+        }
+
+        // Nothing can be merged so it's the most quads a chunk can have
+        let vertices = mesh_chunk(&chunk, &blocks, |_| (AIR, Light::SKY));
+        let quad_count = vertices.len() / 4;
+        assert_eq!(quad_count, MAX_QUADS);
+        assert!(vertices.len() > u16::MAX as usize);
+
+        // The indices drawn for the mesh only index its own vertices
+        let indices = quad_indices(MAX_QUADS);
+        let drawn = &indices[..quad_count * 6];
+        assert!(drawn.iter().all(|i| (*i as usize) < vertices.len()));
+        assert_eq!(*drawn.iter().max().unwrap() as usize, vertices.len() - 1);
+    }
+
+    #[test]
+    fn face_texture_layers() {
+        let blocks = mesh_blocks();
+        let grass = blocks.registry.id("grass").unwrap();
+        assert_eq!(blocks.face_layer(grass, 4), 3);
+        assert_eq!(blocks.face_layer(grass, 5), 1);
+        assert_eq!(blocks.face_layer(grass, 0), 2);
+
+        // Textures that don't exist and unknown blocks
+        let no_textures = MeshBlocks::new(blocks.registry.clone(), &Default::default());
+        assert_eq!(no_textures.face_layer(grass, 4), MISSING_TEXTURE_LAYER);
+        assert_eq!(blocks.face_layer(BlockID::MAX, 0), MISSING_TEXTURE_LAYER);
+
+        // Layers are packed into the vertices
+        let mut chunk = Chunk::new(glam::IVec3::ZERO);
+        chunk.set_block(glam::UVec3::ZERO, grass);
+        let vertices = mesh_chunk(&chunk, &blocks, |_| (AIR, Light::SKY));
+        let top_quad = &vertices[4 * 4..5 * 4];
+        assert!(top_quad.iter().all(|vertex| vertex.0 >> 21 & 0xff == 3));
+    }
+
+    #[test]
+    fn block_textures_found() {
+        // Tests run from the crate directory rather than the workspace one
+        let (images, layers) = load_textures(&texture_dir());
+        assert_eq!(images.len(), layers.len() + 1);
+        for name in ["dirt", "grass_top", "stone"] {
+            assert!(layers.contains_key(name), "{} is missing", name);
+        }
+        let distinct = layers.values().collect::<std::collections::HashSet<_>>();
+        assert_eq!(distinct.len(), layers.len());
+        assert!(!distinct.contains(&MISSING_TEXTURE_LAYER));
+
+        // Entity textures don't take up block layers
+        assert!(!layers.contains_key("player"));
+        let (_, entity_layers) = load_textures(&texture_dir().join(ENTITY_TEXTURE_DIR));
+        assert!(entity_layers.contains_key("player"));
+    }
+
+    #[test]
+    fn ambient_occlusion() {
+        let blocks = mesh_blocks();
+        let stone = blocks.registry.id("stone").unwrap();
+        let top_ao =
+            |chunk: &Chunk, get_outside_block: &dyn Fn(glam::IVec3) -> (BlockID, Light), pos| {
+                visible_face(chunk, &blocks.registry, &get_outside_block, pos, 4)
+                    .unwrap()
+                    .ao
+            };
+
+        let mut chunk = Chunk::new(glam::IVec3::ZERO);
+        chunk.set_block(glam::uvec3(1, 1, 1), stone);
+        assert_eq!(
+            top_ao(&chunk, &|_| (AIR, Light::SKY), glam::ivec3(1, 1, 1)),
+            [3; 4]
+        );
+
+        // A block to the side above darkens the two vertices next to it
+        chunk.set_block(glam::uvec3(0, 2, 1), stone);
+        assert_eq!(
+            top_ao(&chunk, &|_| (AIR, Light::SKY), glam::ivec3(1, 1, 1)),
+            [3, 2, 2, 3]
+        );
+
+        // The vertex between two blocks is fully dark
+        chunk.set_block(glam::uvec3(1, 2, 0), stone);
+        assert_eq!(
+            top_ao(&chunk, &|_| (AIR, Light::SKY), glam::ivec3(1, 1, 1)),
+            [2, 0, 2, 3]
+        );
+
+        // The quad gets split along the darker diagonal by starting from the darkest vertex
+        let vertices = mesh_chunk(&chunk, &blocks, |_| (AIR, Light::SKY));
+        let top_quad = vertices
+            .chunks(4)
+            .find(|quad| quad.iter().all(|vertex| vertex.0 >> 6 & 0x3f == 2))
+            .unwrap();
+        let ao = top_quad
+            .iter()
+            .map(|vertex| vertex.0 >> 29)
+            .collect::<Vec<_>>();
+        assert_eq!(ao, [0, 2, 3, 2]);
+
+        // Blocks in the chunks next to it including diagonal ones occlude across the border
+        let mut chunk = Chunk::new(glam::IVec3::ZERO);
+        chunk.set_block(glam::uvec3(0, 31, 0), stone);
+        let get_outside_block = |pos: glam::IVec3| {
+            let id = if pos.x < 0 { stone } else { AIR };
+            (id, Light::default())
+        };
+        assert_eq!(
+            top_ao(&chunk, &get_outside_block, glam::ivec3(0, 31, 0)),
+            [3, 1, 1, 3]
+        );
+    }
+
+    #[test]
+    fn smooth_light() {
+        let blocks = mesh_blocks();
+        let stone = blocks.registry.id("stone").unwrap();
+        let mut chunk = Chunk::new(glam::IVec3::ZERO);
+        chunk.set_block(glam::uvec3(1, 1, 1), stone);
+        for pos in iter_3d(0, 3).filter(|pos| pos.y == 2) {
+            chunk.set_light(pos.as_uvec3(), Light::new(pos.x as u8 * 4, 0));
+        }
+        chunk.set_light(glam::uvec3(1, 2, 1), Light::new(4, 12));
+        let top_light = |chunk: &Chunk| {
+            visible_face(
+                chunk,
+                &blocks.registry,
+                &|_| (AIR, Light::default()),
+                glam::ivec3(1, 1, 1),
+                4,
+            )
+            .unwrap()
+            .light
+        };
+
+        // Averages the light of the four blocks around each vertex
+        let unpack = |light: u32| (light & 0x3f, light >> 6 & 0x3f);
+        assert_eq!(unpack(top_light(&chunk)[0]), ((4 + 8 + 4 + 8) * 4 / 4, 12));
+        assert_eq!(unpack(top_light(&chunk)[1]), (4 + 4, 12));
+
+        // Light doesn't come through the sides or the corner hidden behind them
+        chunk.set_block(glam::uvec3(0, 2, 1), stone);
+        chunk.set_block(glam::uvec3(1, 2, 0), stone);
+        assert_eq!(unpack(top_light(&chunk)[1]), (4 * 4, 4 * 12));
+    }
 }
@@ -0,0 +1,54 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uvs: vec2<f32>,
+    @location(1) light_level: f32,
+    @location(2) @interpolate(flat) texture_layer: u32,
+};
+
+struct GlobalUniform {
+    view_projection: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> global: GlobalUniform;
+
+// The position of the entity then its yaw in degrees
+var<push_constant> entity_transform: vec4<f32>;
+
+var<private> light_levels: array<f32, 6> = array<f32, 6>(0.8, 0.8, 0.6, 0.6, 1.0, 0.4);
+
+@vertex
+fn vs_main(
+    @location(0) position: vec3<f32>,
+    @location(1) uvs: vec2<f32>,
+    @location(2) dir_index: u32,
+    @location(3) texture_layer: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+
+    // Turn around the y axis so the front faces along the yaw
+    let yaw = radians(entity_transform.w);
+    let turned = vec3<f32>(
+        position.x * cos(yaw) - position.z * sin(yaw),
+        position.y,
+        position.x * sin(yaw) + position.z * cos(yaw),
+    );
+    out.position = global.view_projection * vec4<f32>(turned + entity_transform.xyz, 1.0);
+
+    out.uvs = uvs;
+    out.light_level = light_levels[dir_index];
+    out.texture_layer = texture_layer;
+    return out;
+}
+
+@group(1) @binding(0)
+var diffuse_texture: texture_2d_array<f32>;
+@group(1) @binding(1)
+var diffuse_sampler: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var color = textureSample(diffuse_texture, diffuse_sampler, in.uvs, i32(in.texture_layer)) * in.light_level;
+    color.a = 1.0;
+    return color;
+}
@@ -0,0 +1,189 @@
+use bevy_ecs::prelude::*;
+
+use crate::world::{RemotePlayer, WorldTransform, PLAYER_AABB};
+
+use super::{
+    bind_group::BindGroup,
+    buffer::{new_buffer_quad_index, Buffer},
+    chunk_renderer::{
+        load_textures, new_texture_bind_group, texture_dir, CUBE_INDICES, CUBE_VERTICES,
+        DIRECTION_AXES, ENTITY_TEXTURE_DIR, MISSING_TEXTURE_LAYER,
+    },
+    render_pipeline::RenderPipeline,
+    texture::Texture,
+    MainRenderer, RenderState,
+};
+
+/// Index of the direction entities face when their yaw is 0, which is east
+const FRONT_DIR_INDEX: usize = 2;
+
+#[repr(C)]
+#[derive(Default, Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct Vertex {
+    position: glam::Vec3,
+    uv: glam::Vec2,
+    dir_index: u32,
+    texture_layer: u32,
+}
+
+impl Vertex {
+    const LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<Vertex>() as u64,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: &wgpu::vertex_attr_array![
+            0 => Float32x3, 1 => Float32x2, 2 => Uint32, 3 => Uint32
+        ],
+    };
+}
+
+/// Creates the vertices of a box around the entity position with the texture covering each
+/// face, the front face gets its own texture
+fn mesh_box(min: glam::Vec3, max: glam::Vec3, layer: u32, front_layer: u32) -> Vec<Vertex> {
+    let mut vertices = Vec::new();
+    for (dir_index, &(_, u_axis, v_axis)) in DIRECTION_AXES.iter().enumerate() {
+        let texture_layer = if dir_index == FRONT_DIR_INDEX {
+            front_layer
+        } else {
+            layer
+        };
+
+        for i in 0..4 {
+            let corner = CUBE_VERTICES[CUBE_INDICES[(dir_index * 4) + i]].as_vec3();
+            // Textures go down the sides from the top
+            let v = match v_axis {
+                1 => 1.0 - corner.y,
+                _ => corner[v_axis],
+            };
+            vertices.push(Vertex {
+                position: min + corner * (max - min),
+                uv: glam::vec2(corner[u_axis], v),
+                dir_index: dir_index as u32,
+                texture_layer,
+            });
+        }
+    }
+    vertices
+}
+
+/// Draws other players as textured boxes
+#[derive(Resource)]
+pub struct EntityRenderer {
+    vertex_buffer: Buffer<Vertex>,
+    index_buffer: Buffer<u32>,
+    render_pipeline: RenderPipeline,
+    texture_bind_group: BindGroup,
+}
+
+impl FromWorld for EntityRenderer {
+    fn from_world(world: &mut World) -> Self {
+        let renderer = world.resource::<RenderState>();
+        let device = &renderer.device;
+
+        let (images, texture_layers) = load_textures(&texture_dir().join(ENTITY_TEXTURE_DIR));
+        let texture = Texture::new(device, &renderer.queue, &images);
+        let texture_bind_group = new_texture_bind_group(device, &texture);
+
+        let layer = |name| {
+            texture_layers.get(name).copied().unwrap_or_else(|| {
+                log::warn!("Missing texture {} for players", name);
+                MISSING_TEXTURE_LAYER
+            })
+        };
+        let vertices = mesh_box(
+            PLAYER_AABB.min,
+            PLAYER_AABB.max,
+            layer("player"),
+            layer("player_face"),
+        );
+
+        let render_pipeline = RenderPipeline::new(
+            device,
+            wgpu::include_wgsl!("entity.wgsl"),
+            &[
+                &renderer.global_bind_group.layout,
+                &texture_bind_group.layout,
+            ],
+            &[Vertex::LAYOUT],
+            renderer.config.format,
+            Some(renderer.depth_texture.format),
+            &[wgpu::PushConstantRange {
+                stages: wgpu::ShaderStages::VERTEX,
+                range: 0..16,
+            }],
+        );
+
+        Self {
+            vertex_buffer: Buffer::new(device, wgpu::BufferUsages::VERTEX, &vertices),
+            index_buffer: new_buffer_quad_index(device, DIRECTION_AXES.len()),
+            render_pipeline,
+            texture_bind_group,
+        }
+    }
+}
+
+pub fn entity_render(
+    render_state: Res<RenderState>,
+    mut renderer: ResMut<MainRenderer>,
+    entity_renderer: Res<EntityRenderer>,
+    query: Query<&WorldTransform, With<RemotePlayer>>,
+) {
+    if query.is_empty() {
+        return;
+    }
+
+    let mut render_pass = renderer.begin_render_pass(Some(&render_state.depth_texture.view));
+
+    render_pass.set_pipeline(&entity_renderer.render_pipeline.pipeline);
+    render_pass.set_bind_group(0, &render_state.global_bind_group.group, &[]);
+    render_pass.set_bind_group(1, &entity_renderer.texture_bind_group.group, &[]);
+    render_pass.set_vertex_buffer(0, entity_renderer.vertex_buffer.buf.slice(..));
+    render_pass.set_index_buffer(
+        entity_renderer.index_buffer.buf.slice(..),
+        wgpu::IndexFormat::Uint32,
+    );
+
+    let index_count = entity_renderer.vertex_buffer.len / 4 * 6;
+    for transform in query.iter() {
+        // Only turned by the yaw so the box stays upright
+        let offset = transform.position.extend(transform.rotation.x);
+        render_pass.set_push_constants(
+            wgpu::ShaderStages::VERTEX,
+            0,
+            bytemuck::cast_slice(&[offset]),
+        );
+        render_pass.draw_indexed(0..index_count as u32, 0, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn box_textures() {
+        let vertices = mesh_box(glam::vec3(-1.0, -2.0, -1.0), glam::Vec3::ONE, 1, 2);
+        assert_eq!(vertices.len(), 6 * 4);
+
+        for face in vertices.chunks(4) {
+            let dir_index = face[0].dir_index as usize;
+            let expected_layer = if dir_index == FRONT_DIR_INDEX { 2 } else { 1 };
+            assert!(face
+                .iter()
+                .all(|vertex| vertex.texture_layer == expected_layer));
+
+            // Each face covers the whole texture
+            let (min_uv, max_uv) = face.iter().fold(
+                (glam::Vec2::splat(f32::MAX), glam::Vec2::splat(f32::MIN)),
+                |(min, max), vertex| (min.min(vertex.uv), max.max(vertex.uv)),
+            );
+            assert_eq!((min_uv, max_uv), (glam::Vec2::ZERO, glam::Vec2::ONE));
+        }
+
+        // The top of the front face is the top of the texture
+        let front = &vertices[FRONT_DIR_INDEX * 4..FRONT_DIR_INDEX * 4 + 4];
+        assert!(front
+            .iter()
+            .all(|vertex| vertex.position.x == 1.0
+                && (vertex.position.y == 1.0) == (vertex.uv.y == 0.0)));
+    }
+}
@@ -0,0 +1,83 @@
+/// The space a camera can see, made of the planes on each side of it with their normals pointing
+/// inwards
+pub struct Frustum {
+    /// Plane normal and distance from the origin packed into each
+    planes: [glam::Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from the rows of a view projection matrix with a depth range of 0 to 1
+    pub fn from_view_projection(view_projection: glam::Mat4) -> Self {
+        let x = view_projection.row(0);
+        let y = view_projection.row(1);
+        let z = view_projection.row(2);
+        let w = view_projection.row(3);
+
+        let planes = [w + x, w - x, w + y, w - y, z, w - z].map(|plane| {
+            // Normalised so the distance to the plane can be compared
+            plane / plane.truncate().length()
+        });
+        Self { planes }
+    }
+
+    /// Whether any part of the axis aligned box could be visible
+    pub fn intersects_aabb(&self, min: glam::Vec3, max: glam::Vec3) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the normal is the last one to go outside of the plane
+            let normal = plane.truncate();
+            let corner = glam::Vec3::select(normal.cmpge(glam::Vec3::ZERO), max, min);
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{camera::Camera, world::WorldTransform};
+
+    fn camera_frustum(rotation: glam::Vec2) -> Frustum {
+        let mut camera = Camera::default();
+        camera.resize(1600, 900);
+        let transform = WorldTransform {
+            position: glam::Vec3::ZERO,
+            rotation,
+        };
+        Frustum::from_view_projection(camera.view_projection(transform))
+    }
+
+    fn unit_box(frustum: &Frustum, center: glam::Vec3) -> bool {
+        frustum.intersects_aabb(center - 0.5, center + 0.5)
+    }
+
+    #[test]
+    fn culls_boxes_outside() {
+        // Looking along +x
+        let frustum = camera_frustum(glam::Vec2::ZERO);
+        assert!(unit_box(&frustum, glam::vec3(10.0, 0.0, 0.0)));
+        assert!(!unit_box(&frustum, glam::vec3(-10.0, 0.0, 0.0)));
+        assert!(!unit_box(&frustum, glam::vec3(1100.0, 0.0, 0.0)));
+
+        // 60 degree vertical field of view and wider horizontally
+        assert!(unit_box(&frustum, glam::vec3(10.0, 5.0, 0.0)));
+        assert!(!unit_box(&frustum, glam::vec3(10.0, 7.0, 0.0)));
+        assert!(unit_box(&frustum, glam::vec3(10.0, 0.0, 9.0)));
+        assert!(!unit_box(&frustum, glam::vec3(10.0, 0.0, -12.0)));
+
+        // Boxes partly inside or around the camera are still visible
+        assert!(unit_box(&frustum, glam::vec3(10.0, 6.2, 0.0)));
+        assert!(frustum.intersects_aabb(glam::Vec3::splat(-32.0), glam::Vec3::splat(32.0)));
+    }
+
+    #[test]
+    fn follows_rotation() {
+        // Yaw of 90 degrees looks along +z and pitch of 90 degrees looks up
+        let frustum = camera_frustum(glam::vec2(90.0, 0.0));
+        assert!(unit_box(&frustum, glam::vec3(0.0, 0.0, 10.0)));
+        assert!(!unit_box(&frustum, glam::vec3(10.0, 0.0, 0.0)));
+
+        let frustum = camera_frustum(glam::vec2(0.0, 89.0));
+        assert!(unit_box(&frustum, glam::vec3(0.0, 10.0, 0.0)));
+        assert!(!unit_box(&frustum, glam::vec3(0.0, -10.0, 0.0)));
+    }
+}
@@ -49,7 +49,7 @@ impl RenderState {
                     label: None,
                     features: wgpu::Features::PUSH_CONSTANTS,
                     limits: wgpu::Limits {
-                        max_push_constant_size: 12,
+                        max_push_constant_size: 16,
                         ..Default::default()
                     },
                 },
@@ -109,6 +109,8 @@ struct RenderInstance {
 #[derive(Resource, Default)]
 pub struct MainRenderer {
     instance: Option<RenderInstance>,
+    /// Whether a render pass has cleared the output this frame
+    cleared: bool,
 }
 
 impl MainRenderer {
@@ -126,6 +128,7 @@ impl MainRenderer {
             encoder: device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None }),
             output,
         });
+        self.cleared = false;
 
         Ok(())
     }
@@ -140,16 +143,20 @@ impl MainRenderer {
         instance.output.present();
     }
 
-    // Every render system should call this to start rendering
+    // Every render system should call this to start rendering, only the first render pass in
+    // the frame clears the output
     pub fn begin_render_pass<'a>(
         &'a mut self,
         depth_texture_view: Option<&'a wgpu::TextureView>,
-    ) -> wgpu::RenderPass {
+    ) -> wgpu::RenderPass<'a> {
         let instance = self
             .instance
             .as_mut()
             .expect("Tried to begin render pass calling begin");
 
+        let clear = !self.cleared;
+        self.cleared = true;
+
         instance
             .encoder
             .begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -158,12 +165,15 @@ impl MainRenderer {
                     view: &instance.view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
+                        load: match clear {
+                            true => wgpu::LoadOp::Clear(wgpu::Color {
+                                r: 0.1,
+                                g: 0.2,
+                                b: 0.3,
+                                a: 1.0,
+                            }),
+                            false => wgpu::LoadOp::Load,
+                        },
                         store: true,
                     },
                 })],
@@ -171,7 +181,10 @@ impl MainRenderer {
                     wgpu::RenderPassDepthStencilAttachment {
                         view,
                         depth_ops: Some(wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(1.0),
+                            load: match clear {
+                                true => wgpu::LoadOp::Clear(1.0),
+                                false => wgpu::LoadOp::Load,
+                            },
                             store: true,
                         }),
                         stencil_ops: None,
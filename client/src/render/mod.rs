@@ -1,12 +1,17 @@
 mod bind_group;
 mod buffer;
+mod chunk_mesher;
 mod chunk_renderer;
+mod entity_renderer;
+mod frustum;
 mod main_renderer;
 mod render_pipeline;
 mod texture;
 
 use self::{
-    chunk_renderer::{chunk_mesh_gen, chunk_render, ChunkRenderer},
+    chunk_mesher::ChunkMesher,
+    chunk_renderer::{chunk_mesh_gen, chunk_render, ChunkRenderStats, ChunkRenderer},
+    entity_renderer::{entity_render, EntityRenderer},
     main_renderer::{on_resize, post_render, pre_render, MainRenderer, RenderState},
 };
 use crate::window::Window;
@@ -25,11 +30,15 @@ impl bevy_app::Plugin for Plugin {
             .with_system(chunk_mesh_gen)
             .with_system(pre_render.before(RenderPass))
             .with_system(chunk_render.label(RenderPass))
+            .with_system(entity_render.label(RenderPass))
             .with_system(post_render.after(RenderPass));
 
         let window = app.world.resource::<Window>();
         app.insert_resource(pollster::block_on(RenderState::new(window)))
             .init_resource::<ChunkRenderer>()
+            .init_resource::<EntityRenderer>()
+            .init_resource::<ChunkMesher>()
+            .init_resource::<ChunkRenderStats>()
             .init_resource::<MainRenderer>()
             .add_stage_after(bevy_app::CoreStage::PostUpdate, "render", render_stage);
     }
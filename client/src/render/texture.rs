@@ -1,4 +1,5 @@
 pub struct Texture {
+    #[allow(dead_code)]
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
@@ -6,50 +7,87 @@ pub struct Texture {
 }
 
 impl Texture {
-    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, image: &image::DynamicImage) -> Self {
+    /// Creates a texture array with a layer for each image and generated mipmaps, the images
+    /// get converted to RGBA8 and resized to the size of the first image
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, images: &[image::DynamicImage]) -> Self {
+        use image::imageops::{self, FilterType};
+
+        let (width, height) = (images[0].width(), images[0].height());
+        let layers = images.iter().map(|image| {
+            let image = image.to_rgba8();
+            if image.dimensions() == (width, height) {
+                image
+            } else {
+                imageops::resize(&image, width, height, FilterType::Nearest)
+            }
+        });
+
         let size = wgpu::Extent3d {
-            width: image.width(),
-            height: image.height(),
-            depth_or_array_layers: 1,
+            width,
+            height,
+            depth_or_array_layers: images.len() as u32,
         };
+        // Halve the size until it's 1 pixel
+        let mip_level_count = u32::BITS - width.max(height).leading_zeros();
 
         let format = wgpu::TextureFormat::Rgba8UnormSrgb;
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: None,
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
         });
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            image
-                .as_rgba8()
-                .expect("Only 8-bit RGBA images are supported!"),
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: std::num::NonZeroU32::new(4 * image.width()),
-                rows_per_image: std::num::NonZeroU32::new(image.height()),
-            },
-            size,
-        );
+        for (layer, image) in layers.enumerate() {
+            let mut mip = image;
+            for mip_level in 0..mip_level_count {
+                if mip_level > 0 {
+                    let mip_width = (mip.width() / 2).max(1);
+                    let mip_height = (mip.height() / 2).max(1);
+                    mip = imageops::resize(&mip, mip_width, mip_height, FilterType::Triangle);
+                }
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        texture: &texture,
+                        mip_level,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: layer as u32,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    &mip,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: std::num::NonZeroU32::new(4 * mip.width()),
+                        rows_per_image: std::num::NonZeroU32::new(mip.height()),
+                    },
+                    wgpu::Extent3d {
+                        width: mip.width(),
+                        height: mip.height(),
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        // Repeats so the texture can tile across faces covering multiple blocks
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
             mag_filter: wgpu::FilterMode::Nearest,
             min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Linear,
             ..Default::default()
         });
 
@@ -76,7 +114,7 @@ impl Texture {
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
         };
         let texture = device.create_texture(&desc);
 
@@ -1,3 +1,4 @@
+use bevy_app::AppExit;
 use bevy_ecs::{event::Events, system::Resource};
 use winit::{
     dpi::PhysicalSize,
@@ -72,7 +73,12 @@ fn runner(mut app: bevy_app::App) {
         match event {
             Event::MainEventsCleared => app.update(),
             Event::WindowEvent { ref event, .. } => match event {
-                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                WindowEvent::CloseRequested => {
+                    // Run a last update so systems can react to the app exiting
+                    world.send_event(AppExit);
+                    app.update();
+                    *control_flow = ControlFlow::Exit;
+                }
                 WindowEvent::Resized(size) => {
                     let mut events = world.resource_mut::<Events<WindowResize>>();
                     events.send(WindowResize { size: *size });
@@ -1,99 +1,366 @@
 use std::collections::VecDeque;
 
 use bevy_ecs::prelude::*;
-use noise::NoiseFn;
-use opencuboids_common::{in_bounds, iter_3d_vec, BlockID, Chunk, CHUNK_SIZE};
+use opencuboids_common::{
+    block_to_chunk_pos, block_to_local_pos, in_bounds, iter_3d, iter_3d_vec, network::Request,
+    Biome, BlockID, Chunk, Light, CHUNK_SIZE,
+};
 
-use super::{physics::WorldTransform, Player};
+use super::{physics::WorldTransform, Blocks, Player};
+use crate::network::StreamChannel;
 
+/// Chunks closer than this get meshed, chunks one further are loaded to get the neighbours of
+/// the meshed chunks on the edges
 pub const RENDER_DISTANCE: i32 = 5;
 
 #[derive(Default, Resource)]
 pub struct ChunkManager {
     pub chunk_update_queue: VecDeque<glam::IVec3>,
-    pub chunks_left_loading: u32,
     pub chunk_map: bevy_utils::HashMap<glam::IVec3, Chunk>,
     pub chunk_pos_center: Option<glam::IVec3>,
+    /// Chunks requested from the server that haven't arrived yet
+    pub pending_chunks: bevy_utils::HashSet<glam::IVec3>,
+    /// Chunks that have been queued to be meshed
+    pub meshed_chunks: bevy_utils::HashSet<glam::IVec3>,
+    /// The biome of each block column in the columns of chunks around the player
+    pub column_biomes: bevy_utils::HashMap<glam::IVec2, Vec<Biome>>,
 }
 
 impl ChunkManager {
-    pub fn get_block(&self, pos: glam::IVec3) -> BlockID {
-        let chunk_pos = (pos.as_vec3() / CHUNK_SIZE as f32).floor().as_ivec3();
+    /// Gets the block at a world block position if its chunk is loaded
+    pub fn try_get_block(&self, pos: glam::IVec3) -> Option<BlockID> {
         self.chunk_map
-            .get(&chunk_pos)
-            .unwrap()
-            .get_block(pos.as_uvec3() % CHUNK_SIZE as u32)
+            .get(&block_to_chunk_pos(pos))
+            .map(|chunk| chunk.get_block(block_to_local_pos(pos)))
+    }
+
+    /// Gets the biome of the block column if the server has sent it
+    pub fn biome_at(&self, pos: glam::IVec3) -> Option<Biome> {
+        let chunk_pos = block_to_chunk_pos(pos);
+        let local_pos = block_to_local_pos(pos);
+        self.column_biomes
+            .get(&glam::ivec2(chunk_pos.x, chunk_pos.z))
+            .and_then(|biomes| biomes.get(local_pos.x as usize + local_pos.z as usize * CHUNK_SIZE))
+            .copied()
+    }
+
+    pub fn handle_column_biomes(&mut self, column: glam::IVec2, biomes: Vec<Biome>) {
+        if biomes.len() == CHUNK_SIZE * CHUNK_SIZE {
+            self.column_biomes.insert(column, biomes);
+        } else {
+            log::warn!("Got {} biomes for the column {}", biomes.len(), column);
+        }
     }
 
     pub fn handle_chunk_response(&mut self, chunk: Chunk) {
-        self.chunk_map.insert(chunk.pos, chunk);
-        log::info!("Loading {}", self.chunks_left_loading);
-        self.chunks_left_loading -= 1;
+        let chunk_pos = chunk.pos;
+        if self.pending_chunks.remove(&chunk_pos) {
+            self.chunk_map.insert(chunk_pos, chunk);
+
+            // The chunk or its neighbours might have been waiting on this chunk to be meshed
+            for offset in iter_3d(-1, 2) {
+                self.queue_mesh(chunk_pos + offset);
+            }
+        }
+        // Otherwise the chunk was requested before the player moved away and isn't needed anymore
+    }
+
+    pub fn handle_block_update(&mut self, pos: glam::IVec3, id: BlockID) {
+        let chunk_pos = block_to_chunk_pos(pos);
+        let local_pos = block_to_local_pos(pos);
+        let Some(chunk) = self.chunk_map.get_mut(&chunk_pos) else {
+            return;
+        };
+
+        chunk.set_block(local_pos, id);
+        for offset in touching_chunks(local_pos) {
+            self.queue_remesh(chunk_pos + offset);
+        }
+    }
+
+    pub fn handle_light_update(&mut self, lights: Vec<(glam::IVec3, Light)>) {
+        let mut remesh_chunks = bevy_utils::HashSet::default();
+        for (pos, light) in lights {
+            let chunk_pos = block_to_chunk_pos(pos);
+            let local_pos = block_to_local_pos(pos);
+            let Some(chunk) = self.chunk_map.get_mut(&chunk_pos) else {
+                continue;
+            };
+
+            chunk.set_light(local_pos, light);
+            remesh_chunks.extend(touching_chunks(local_pos).map(|offset| chunk_pos + offset));
+        }
+
+        for chunk_pos in remesh_chunks {
+            self.queue_remesh(chunk_pos);
+        }
+    }
+
+    /// Queues a chunk that has already been meshed to be meshed again, chunks that haven't been
+    /// meshed yet will have the change once they are
+    fn queue_remesh(&mut self, chunk_pos: glam::IVec3) {
+        if self.meshed_chunks.contains(&chunk_pos) && !self.chunk_update_queue.contains(&chunk_pos)
+        {
+            // Changes made by players should show up before new chunks
+            self.chunk_update_queue.push_front(chunk_pos);
+        }
+    }
+
+    /// Queues the chunk to be meshed once it and all of its neighbours have arrived, including
+    /// the diagonal ones
+    fn queue_mesh(&mut self, chunk_pos: glam::IVec3) {
+        let Some(center) = self.chunk_pos_center else {
+            return;
+        };
+
+        if !in_bounds(chunk_pos, center, RENDER_DISTANCE)
+            || self.meshed_chunks.contains(&chunk_pos)
+            || !self.chunk_map.contains_key(&chunk_pos)
+        {
+            return;
+        }
+
+        if iter_3d(-1, 2).all(|offset| self.chunk_map.contains_key(&(chunk_pos + offset))) {
+            self.meshed_chunks.insert(chunk_pos);
+            self.chunk_update_queue.push_back(chunk_pos);
+        }
+    }
+}
+
+/// Gets the offsets of the chunks that a block in a chunk changes the mesh of, blocks on the
+/// border of the chunk also change the faces, ambient occlusion and light of the neighbouring
+/// chunks it touches, including the ones diagonal to it
+fn touching_chunks(local_pos: glam::UVec3) -> impl Iterator<Item = glam::IVec3> {
+    iter_3d(-1, 2)
+        .filter(move |offset| block_to_chunk_pos(local_pos.as_ivec3() + *offset) == *offset)
+}
+
+/// Splits the chunks into boxes of neighbouring chunks, growing each box along x, then z, then y
+/// for as long as every chunk in it is part of the set
+fn chunk_ranges(chunks: &bevy_utils::HashSet<glam::IVec3>) -> Vec<(glam::IVec3, glam::IVec3)> {
+    let mut sorted_chunks = chunks.iter().copied().collect::<Vec<_>>();
+    sorted_chunks.sort_by_key(|pos| (pos.y, pos.z, pos.x));
+
+    let mut remaining = chunks.clone();
+    let mut ranges = Vec::new();
+    for start in sorted_chunks {
+        if !remaining.contains(&start) {
+            continue;
+        }
+
+        let all_remaining = |start: glam::IVec3, end: glam::IVec3| {
+            iter_3d_vec(start, end).all(|pos| remaining.contains(&pos))
+        };
+        let mut end = start + 1;
+        while all_remaining(glam::ivec3(end.x, start.y, start.z), end + glam::IVec3::X) {
+            end.x += 1;
+        }
+        while all_remaining(glam::ivec3(start.x, start.y, end.z), end + glam::IVec3::Z) {
+            end.z += 1;
+        }
+        while all_remaining(glam::ivec3(start.x, end.y, start.z), end + glam::IVec3::Y) {
+            end.y += 1;
+        }
+
+        for pos in iter_3d_vec(start, end) {
+            remaining.remove(&pos);
+        }
+        ranges.push((start, end));
     }
+    ranges
 }
 
 pub fn chunk_update(
     mut chunk_manager: ResMut<ChunkManager>,
+    channel: Res<StreamChannel>,
+    blocks: Option<Res<Blocks>>,
     player_query: Query<(&WorldTransform, With<Player>)>,
 ) {
+    // Wait until the server has accepted the connection and set the spawn position
+    if blocks.is_none() {
+        return;
+    }
     let (player_trans, _) = player_query.single();
 
     // If the player has moved into a different chunk
-    let player_chunk_pos = player_trans.position.as_ivec3() / CHUNK_SIZE as i32;
-    if chunk_manager
-        .chunk_pos_center
-        .map_or(true, |pos| player_chunk_pos != pos)
-    {
-        // Remove chunks not in the new bounds
-        chunk_manager
-            .chunk_map
-            .drain_filter(|pos, _| in_bounds(*pos, player_chunk_pos, RENDER_DISTANCE));
-
-        // Get chunks 1 more chunk pos than renderered to handle chunk neighbours on the edges
-        let start = player_chunk_pos - RENDER_DISTANCE;
-        let end = player_chunk_pos + RENDER_DISTANCE;
-
-        // Only client side for now because not working network
-        for chunk_pos in iter_3d_vec(start, end) {
-            if !chunk_manager.chunk_map.contains_key(&chunk_pos) {
-                let mut chunk = Chunk::new(chunk_pos);
-                gen_blocks(&mut chunk, chunk_pos);
-                chunk_manager.chunk_map.insert(chunk.pos, chunk);
-            }
-        }
+    let player_chunk_pos = (player_trans.position / CHUNK_SIZE as f32)
+        .floor()
+        .as_ivec3();
+    if chunk_manager.chunk_pos_center == Some(player_chunk_pos) {
+        return;
+    }
 
-        // Loop around in a spiral adding chunks meshes
-        for i in 0..RENDER_DISTANCE {
-            let start_pos = player_chunk_pos - i;
-            let end_pos = player_chunk_pos + i;
-            for chunk_pos in iter_3d_vec(start_pos, end_pos) {
-                // Only create the mesh if it's outside the previous center pos
-                if chunk_manager.chunk_pos_center.map_or(true, |center_pos| {
-                    !in_bounds(chunk_pos, center_pos, RENDER_DISTANCE)
-                }) {
-                    chunk_manager.chunk_update_queue.push_back(chunk_pos);
-                }
-            }
-        }
+    let chunk_manager = &mut *chunk_manager;
+    chunk_manager.chunk_pos_center = Some(player_chunk_pos);
+
+    // Remove chunks not in the new bounds
+    let load_in_bounds = |pos: &glam::IVec3| in_bounds(*pos, player_chunk_pos, RENDER_DISTANCE + 1);
+    let mesh_in_bounds = |pos: &glam::IVec3| in_bounds(*pos, player_chunk_pos, RENDER_DISTANCE);
+    let unloaded_chunks = chunk_manager
+        .chunk_map
+        .keys()
+        .chain(&chunk_manager.pending_chunks)
+        .filter(|pos| !load_in_bounds(pos))
+        .copied()
+        .collect::<Vec<_>>();
+    chunk_manager.chunk_map.retain(|pos, _| load_in_bounds(pos));
+    chunk_manager.pending_chunks.retain(load_in_bounds);
+    chunk_manager.meshed_chunks.retain(mesh_in_bounds);
+    chunk_manager.chunk_update_queue.retain(mesh_in_bounds);
+    chunk_manager
+        .column_biomes
+        .retain(|column, _| load_in_bounds(&glam::ivec3(column.x, player_chunk_pos.y, column.y)));
+
+    // So the server stops sending updates for them and can unload them once no one needs them
+    if !unloaded_chunks.is_empty() {
+        let _ = channel.sender.send(Request::UnloadChunks(unloaded_chunks));
+    }
+
+    // Request the missing chunks in as few ranges as possible, the server generates the ones
+    // closest to the player first
+    let start = player_chunk_pos - RENDER_DISTANCE;
+    let end = player_chunk_pos + RENDER_DISTANCE + 1;
+    let missing_chunks = iter_3d_vec(start, end)
+        .filter(|pos| {
+            !chunk_manager.chunk_map.contains_key(pos)
+                && !chunk_manager.pending_chunks.contains(pos)
+        })
+        .collect::<bevy_utils::HashSet<_>>();
 
-        chunk_manager.chunk_pos_center = Some(player_chunk_pos);
+    for (start, end) in chunk_ranges(&missing_chunks) {
+        // The connection thread logs why if the connection is gone
+        let _ = channel.sender.send(Request::ChunkRange { start, end });
+    }
+    chunk_manager.pending_chunks.extend(missing_chunks);
+
+    // Already loaded chunks might have all their neighbours now
+    let start = player_chunk_pos - RENDER_DISTANCE + 1;
+    let end = player_chunk_pos + RENDER_DISTANCE;
+    for chunk_pos in iter_3d_vec(start, end) {
+        chunk_manager.queue_mesh(chunk_pos);
     }
 }
 
-pub fn gen_blocks(chunk: &mut Chunk, chunk_pos: glam::IVec3) {
-    let start_pos = chunk_pos * CHUNK_SIZE as i32;
-    let end_pos = start_pos + CHUNK_SIZE as i32;
-    let perlin = noise::Perlin::new(10000);
-
-    for x in start_pos.x..end_pos.x {
-        for z in start_pos.z..end_pos.z {
-            let noise_y = perlin.get([x as f64 / 32.0, z as f64 / 32.0]) * 16.0;
-            for y in start_pos.y..end_pos.y {
-                if y < noise_y as i32 {
-                    let local_pos = glam::ivec3(x, y, z).as_uvec3() % CHUNK_SIZE as u32;
-                    chunk.set_block(local_pos, 1);
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_ranges_cover(chunks: &bevy_utils::HashSet<glam::IVec3>, ranges: usize) {
+        let found_ranges = chunk_ranges(chunks);
+        let mut covered = bevy_utils::HashSet::default();
+        for (start, end) in &found_ranges {
+            for pos in iter_3d_vec(*start, *end) {
+                assert!(covered.insert(pos), "{} is in more than one range", pos);
             }
         }
+        assert_eq!(&covered, chunks);
+        assert_eq!(found_ranges.len(), ranges);
+    }
+
+    #[test]
+    fn contiguous_chunks_requested_together() {
+        let start = glam::ivec3(-5, -5, -5);
+        let end = glam::ivec3(6, 6, 6);
+        let all_chunks = iter_3d_vec(start, end).collect::<bevy_utils::HashSet<_>>();
+        assert_ranges_cover(&all_chunks, 1);
+
+        // A slab is left missing after moving into the next chunk
+        let slab = all_chunks
+            .iter()
+            .copied()
+            .filter(|pos| pos.x == 5)
+            .collect();
+        assert_ranges_cover(&slab, 1);
+
+        // The chunks around one that's still loading
+        let mut hollow = all_chunks.clone();
+        hollow.remove(&glam::IVec3::ZERO);
+        assert_ranges_cover(&hollow, 6);
+
+        assert_ranges_cover(&Default::default(), 0);
+    }
+
+    #[test]
+    fn chunks_meshed_once_neighbours_arrive() {
+        let mut chunk_manager = ChunkManager {
+            chunk_pos_center: Some(glam::IVec3::ZERO),
+            pending_chunks: iter_3d(-1, 2).collect(),
+            ..Default::default()
+        };
+
+        // Chunks that aren't pending anymore get dropped
+        chunk_manager.handle_chunk_response(Chunk::new(glam::ivec3(3, 0, 0)));
+        assert!(chunk_manager.chunk_map.is_empty());
+
+        let neighbours = iter_3d(-1, 2).filter(|pos| *pos != glam::IVec3::ZERO);
+        for chunk_pos in [glam::IVec3::ZERO].into_iter().chain(neighbours) {
+            assert!(chunk_manager.chunk_update_queue.is_empty());
+            chunk_manager.handle_chunk_response(Chunk::new(chunk_pos));
+            assert!(!chunk_manager.pending_chunks.contains(&chunk_pos));
+        }
+
+        // Only the center has all of its neighbours
+        assert!(chunk_manager.pending_chunks.is_empty());
+        assert_eq!(chunk_manager.chunk_update_queue, [glam::IVec3::ZERO]);
+        assert_eq!(
+            chunk_manager.meshed_chunks,
+            [glam::IVec3::ZERO].into_iter().collect()
+        );
+
+        // Not meshed again when the chunks around it arrive
+        chunk_manager.chunk_update_queue.clear();
+        chunk_manager.pending_chunks.insert(glam::ivec3(2, 0, 0));
+        chunk_manager.handle_chunk_response(Chunk::new(glam::ivec3(2, 0, 0)));
+        assert!(chunk_manager.chunk_update_queue.is_empty());
+    }
+
+    #[test]
+    fn light_updates_remesh() {
+        let mut chunk_manager = ChunkManager {
+            chunk_pos_center: Some(glam::IVec3::ZERO),
+            chunk_map: [(glam::IVec3::ZERO, Chunk::new(glam::IVec3::ZERO))]
+                .into_iter()
+                .collect(),
+            meshed_chunks: iter_3d(-1, 2).collect(),
+            ..Default::default()
+        };
+
+        // Blocks on the border change the light of the neighbour's mesh too, light in chunks
+        // that aren't loaded gets ignored
+        let light = Light::new(3, 7);
+        chunk_manager.handle_light_update(vec![
+            (glam::ivec3(31, 5, 5), light),
+            (glam::ivec3(40, 5, 5), light),
+        ]);
+        assert_eq!(
+            chunk_manager.chunk_map[&glam::IVec3::ZERO].get_light(glam::uvec3(31, 5, 5)),
+            light
+        );
+        let mut queued = chunk_manager.chunk_update_queue.iter().collect::<Vec<_>>();
+        queued.sort_by_key(|pos| pos.x);
+        assert_eq!(queued, [&glam::IVec3::ZERO, &glam::IVec3::X]);
+    }
+
+    #[test]
+    fn biomes_looked_up() {
+        let mut chunk_manager = ChunkManager::default();
+        let mut biomes = vec![Biome::Plains; CHUNK_SIZE * CHUNK_SIZE];
+        biomes[3 + 30 * CHUNK_SIZE] = Biome::Desert;
+        chunk_manager.handle_column_biomes(glam::ivec2(-1, 0), biomes);
+
+        // Any height in the column, the columns next to it haven't been sent
+        assert_eq!(
+            chunk_manager.biome_at(glam::ivec3(-29, 500, 30)),
+            Some(Biome::Desert)
+        );
+        assert_eq!(
+            chunk_manager.biome_at(glam::ivec3(-1, -40, 0)),
+            Some(Biome::Plains)
+        );
+        assert_eq!(chunk_manager.biome_at(glam::ivec3(0, 0, 0)), None);
+
+        // Ones with the wrong size get ignored
+        chunk_manager.handle_column_biomes(glam::ivec2(0, 0), vec![Biome::Forest]);
+        assert_eq!(chunk_manager.biome_at(glam::ivec3(0, 0, 0)), None);
     }
 }
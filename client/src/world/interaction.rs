@@ -0,0 +1,100 @@
+use bevy_ecs::prelude::*;
+use opencuboids_common::{network::Request, raycast, RaycastHit, AIR};
+use winit::event::{MouseButton, VirtualKeyCode};
+
+use super::{Blocks, ChunkManager, Player, WorldTransform};
+use crate::{camera::Camera, input::Input, network::StreamChannel, window::Window};
+
+/// How far away blocks can be broken or placed, less than the server allows
+const REACH: f32 = 6.0;
+
+const BLOCK_SELECT_KEYS: &[VirtualKeyCode] = &[
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5,
+    VirtualKeyCode::Key6,
+    VirtualKeyCode::Key7,
+    VirtualKeyCode::Key8,
+    VirtualKeyCode::Key9,
+];
+
+/// The solid block the player is looking at
+#[derive(Default, Resource)]
+pub struct TargetBlock(pub Option<RaycastHit>);
+
+pub fn update_target_block(
+    chunk_manager: Res<ChunkManager>,
+    blocks: Option<Res<Blocks>>,
+    mut target_block: ResMut<TargetBlock>,
+    camera_query: Query<&WorldTransform, (With<Player>, With<Camera>)>,
+) {
+    let Some(blocks) = blocks else {
+        return;
+    };
+
+    let transform = camera_query.single();
+    target_block.0 = raycast(transform.position, transform.front(), REACH, |pos| {
+        // Blocks in chunks that haven't loaded yet can't be targeted
+        chunk_manager
+            .try_get_block(pos)
+            .is_some_and(|id| blocks.is_solid(id))
+    });
+}
+
+/// Breaks the targeted block with left click and places the selected block with right click,
+/// the server sends back the change if it's allowed
+pub fn block_interaction(
+    input: Res<Input>,
+    window: Res<Window>,
+    channel: Res<StreamChannel>,
+    target_block: Res<TargetBlock>,
+    blocks: Option<Res<Blocks>>,
+    mut selected: Local<usize>,
+) {
+    let Some(blocks) = blocks else {
+        return;
+    };
+
+    // Blocks that can be placed are the ones that get rendered
+    let placeable = blocks
+        .iter()
+        .filter(|block| block.textures.is_some())
+        .map(|block| block.id)
+        .collect::<Vec<_>>();
+    for (i, key) in BLOCK_SELECT_KEYS.iter().enumerate() {
+        if input.is_key_just_pressed(*key) && i < placeable.len() {
+            *selected = i;
+        }
+    }
+
+    let break_block = input.is_mouse_just_pressed(MouseButton::Left);
+    let place_block = input.is_mouse_just_pressed(MouseButton::Right);
+    if !window.mouse_locked() || !(break_block || place_block) {
+        return;
+    }
+
+    let Some(hit) = target_block.0 else {
+        return;
+    };
+
+    let request = if break_block {
+        Request::SetBlock {
+            pos: hit.block_pos,
+            id: AIR,
+        }
+    } else if let Some(id) = placeable.get(*selected) {
+        // Place against the face that was hit, there isn't one when inside of the block
+        if hit.normal == glam::IVec3::ZERO {
+            return;
+        }
+        Request::SetBlock {
+            pos: hit.block_pos + hit.normal,
+            id: *id,
+        }
+    } else {
+        return;
+    };
+    let _ = channel.sender.send(request);
+}
@@ -1,27 +1,53 @@
 mod chunk_manager;
+mod interaction;
 mod physics;
 mod player;
+mod remote_player;
 
 use crate::camera::Camera;
 use bevy_ecs::prelude::*;
+use opencuboids_common::BlockRegistry;
+use std::sync::Arc;
 
 use self::{
     chunk_manager::chunk_update,
+    interaction::{block_interaction, update_target_block},
     physics::physics,
-    player::{mouse_lock, player_movement, Player},
+    player::{log_biome, mouse_lock, player_movement},
+    remote_player::interpolate_remote_players,
 };
 pub use self::{
     chunk_manager::{ChunkManager, RENDER_DISTANCE},
-    physics::{PhysicsBody, WorldTransform},
+    interaction::TargetBlock,
+    physics::{Aabb, PhysicsBody, WorldTransform},
+    player::{Player, PLAYER_AABB},
+    remote_player::{RemotePlayer, RemotePlayers},
 };
 
+/// The block registry received from the server
+#[derive(Resource)]
+pub struct Blocks(pub Arc<BlockRegistry>);
+
+impl std::ops::Deref for Blocks {
+    type Target = BlockRegistry;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
 fn spawn(mut commands: Commands) {
     commands.spawn((
         WorldTransform {
             rotation: glam::vec2(f32::to_radians(90.0), 0.0),
             ..Default::default()
         },
-        PhysicsBody::default(),
+        // Start flying so the player doesn't get stuck if spawned inside of the ground
+        PhysicsBody {
+            flying: true,
+            ..Default::default()
+        },
+        PLAYER_AABB,
         Camera::default(),
         Player,
     ));
@@ -33,10 +59,16 @@ pub struct Plugin;
 impl bevy_app::Plugin for Plugin {
     fn build(&self, app: &mut bevy_app::App) {
         app.init_resource::<ChunkManager>()
+            .init_resource::<TargetBlock>()
+            .init_resource::<RemotePlayers>()
             .add_startup_system(spawn)
             .add_system(chunk_update)
             .add_system(player_movement.before(physics))
             .add_system(physics)
-            .add_system(mouse_lock);
+            .add_system(mouse_lock)
+            .add_system(log_biome)
+            .add_system(update_target_block.after(physics))
+            .add_system(block_interaction.after(update_target_block))
+            .add_system(interpolate_remote_players);
     }
 }
@@ -1,5 +1,7 @@
 use bevy_ecs::prelude::*;
+use opencuboids_common::MAX_FALL_SPEED;
 
+use super::{Blocks, ChunkManager};
 use crate::time::Time;
 
 #[derive(Component, Clone, Copy, Default)]
@@ -8,20 +10,232 @@ pub struct WorldTransform {
     pub rotation: glam::Vec2,
 }
 
+impl WorldTransform {
+    /// The direction the transform is facing, rotation is the yaw then pitch in degrees
+    pub fn front(&self) -> glam::Vec3 {
+        let yaw = self.rotation.x.to_radians();
+        let pitch = self.rotation.y.to_radians();
+        glam::vec3(
+            yaw.cos() * pitch.cos(),
+            pitch.sin(),
+            yaw.sin() * pitch.cos(),
+        )
+        .normalize()
+    }
+}
+
 #[derive(Component, Default)]
 pub struct PhysicsBody {
     pub velocity: glam::Vec3,
     pub force: glam::Vec3,
+    /// Flying bodies ignore gravity and pass through blocks
+    pub flying: bool,
+    /// Whether the body is standing on a solid block
+    pub grounded: bool,
+}
+
+/// Box around the position of an entity that stops it from moving into solid blocks
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: glam::Vec3,
+    pub max: glam::Vec3,
+}
+
+impl Aabb {
+    pub fn translate(self, offset: glam::Vec3) -> Self {
+        Self {
+            min: self.min + offset,
+            max: self.max + offset,
+        }
+    }
+}
+
+/// Keeps boxes that are touching a block face from counting as overlapping it
+const EPSILON: f32 = 1e-4;
+
+/// Moves the box by the offset one axis at a time, stopping each axis at the first solid block
+/// in the way, returns the offset that was moved and which axes hit a block
+pub fn move_and_collide(
+    aabb: Aabb,
+    offset: glam::Vec3,
+    is_solid: impl Fn(glam::IVec3) -> bool,
+) -> (glam::Vec3, glam::BVec3) {
+    let mut aabb = aabb;
+    let mut moved = glam::Vec3::ZERO;
+    let mut collided = [false; 3];
+
+    // Vertical first so walking off an edge or onto a floor doesn't snag on the sides
+    for axis in [1, 0, 2] {
+        let mut distance = offset[axis];
+        if distance == 0.0 {
+            continue;
+        }
+
+        // Blocks the box covers on the other two axes
+        let (axis_a, axis_b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let cross_range = |i: usize| {
+            (aabb.min[i] + EPSILON).floor() as i32..=(aabb.max[i] - EPSILON).ceil() as i32 - 1
+        };
+        let slab_is_solid = |layer: i32| {
+            cross_range(axis_a).any(|a| {
+                cross_range(axis_b).any(|b| {
+                    let mut pos = glam::IVec3::ZERO;
+                    pos[axis] = layer;
+                    pos[axis_a] = a;
+                    pos[axis_b] = b;
+                    is_solid(pos)
+                })
+            })
+        };
+
+        // Check each layer of blocks the box sweeps through in the order it reaches them
+        if distance > 0.0 {
+            let start = (aabb.max[axis] - EPSILON).ceil() as i32;
+            let end = (aabb.max[axis] + distance).ceil() as i32;
+            if let Some(layer) = (start..end).find(|layer| slab_is_solid(*layer)) {
+                distance = layer as f32 - aabb.max[axis];
+                collided[axis] = true;
+            }
+        } else {
+            let start = (aabb.min[axis] + EPSILON).floor() as i32 - 1;
+            let end = (aabb.min[axis] + distance).floor() as i32;
+            if let Some(layer) = (end..=start).rev().find(|layer| slab_is_solid(*layer)) {
+                distance = (layer + 1) as f32 - aabb.min[axis];
+                collided[axis] = true;
+            }
+        }
+
+        let mut axis_offset = glam::Vec3::ZERO;
+        axis_offset[axis] = distance;
+        aabb = aabb.translate(axis_offset);
+        moved[axis] = distance;
+    }
+
+    (
+        moved,
+        glam::BVec3::new(collided[0], collided[1], collided[2]),
+    )
+}
+
+impl PhysicsBody {
+    /// Applies the force, friction and gravity to the velocity over the time
+    fn accelerate(&mut self, delta: f32) {
+        const FRICTION: f32 = 20.0;
+        const GRAVITY: f32 = 30.0;
+
+        let mut friction_force = self.velocity * f32::min(FRICTION * delta, 1.0);
+        if !self.flying {
+            friction_force.y = 0.0;
+            self.velocity.y -= GRAVITY * delta;
+        }
+        self.velocity = self.velocity + self.force - friction_force;
+        self.force = glam::Vec3::ZERO;
+
+        // Any faster and the server corrects long falls
+        if !self.flying {
+            self.velocity.y = self.velocity.y.max(-MAX_FALL_SPEED);
+        }
+    }
 }
 
-pub fn physics(time: Res<Time>, mut query: Query<(&mut WorldTransform, &mut PhysicsBody)>) {
-    const FRICTION: f32 = 20.0;
+pub fn physics(
+    time: Res<Time>,
+    chunk_manager: Res<ChunkManager>,
+    blocks: Option<Res<Blocks>>,
+    mut query: Query<(&mut WorldTransform, &mut PhysicsBody, Option<&Aabb>)>,
+) {
+    // Blocks that haven't loaded yet are solid so nothing falls out of the world
+    let is_solid = |pos: glam::IVec3| match (&blocks, chunk_manager.try_get_block(pos)) {
+        (Some(blocks), Some(id)) => blocks.is_solid(id),
+        _ => true,
+    };
 
     let delta = time.delta.as_secs_f32();
-    for (mut transform, mut body) in query.iter_mut() {
-        let friction_force = body.velocity * f32::min(FRICTION * delta, 1.0);
-        body.velocity = body.velocity + body.force - friction_force;
-        transform.position += body.velocity * delta;
-        body.force = glam::Vec3::ZERO;
+    for (mut transform, mut body, aabb) in query.iter_mut() {
+        body.accelerate(delta);
+        let offset = body.velocity * delta;
+        match aabb {
+            Some(aabb) if !body.flying => {
+                let aabb = aabb.translate(transform.position);
+                let (moved, collided) = move_and_collide(aabb, offset, is_solid);
+                transform.position += moved;
+
+                body.grounded = collided.y && offset.y < 0.0;
+                body.velocity = glam::Vec3::select(collided, glam::Vec3::ZERO, body.velocity);
+            }
+            _ => {
+                transform.position += offset;
+                body.grounded = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYER: Aabb = Aabb {
+        min: glam::vec3(-0.3, 0.0, -0.3),
+        max: glam::vec3(0.3, 1.8, 0.3),
+    };
+
+    #[test]
+    fn falling_speed_limited() {
+        let mut body = PhysicsBody::default();
+        for _ in 0..600 {
+            body.accelerate(1.0 / 60.0);
+        }
+        assert_eq!(body.velocity.y, -MAX_FALL_SPEED);
+
+        // Jumping still works after a fall
+        body.velocity.y = 9.0;
+        body.accelerate(1.0 / 60.0);
+        assert!(body.velocity.y > 0.0);
+    }
+
+    #[test]
+    fn lands_on_floor() {
+        let floor = |pos: glam::IVec3| pos.y < 0;
+        let aabb = PLAYER.translate(glam::vec3(0.5, 2.5, 0.5));
+        let (moved, collided) = move_and_collide(aabb, glam::vec3(0.0, -10.0, 0.0), floor);
+        assert_eq!(moved, glam::vec3(0.0, -2.5, 0.0));
+        assert!(collided.y && !collided.x && !collided.z);
+
+        // Standing on the floor stops falling straight away but can still walk
+        let aabb = aabb.translate(moved);
+        let (moved, collided) = move_and_collide(aabb, glam::vec3(3.0, -0.1, 0.0), floor);
+        assert_eq!(moved, glam::vec3(3.0, 0.0, 0.0));
+        assert!(collided.y && !collided.x);
+    }
+
+    #[test]
+    fn stops_at_walls() {
+        // Wall at x = -5 with a hole above the box
+        let wall = |pos: glam::IVec3| pos.x == -5 && pos.y != 3;
+        let aabb = PLAYER.translate(glam::vec3(0.0, 0.5, 0.0));
+        let (moved, collided) = move_and_collide(aabb, glam::vec3(-20.0, 0.0, 2.0), wall);
+        assert!((moved.x - -3.7).abs() < 1e-5);
+        assert_eq!(moved.z, 2.0);
+        assert!(collided.x && !collided.z);
+
+        // Walking along the wall while touching it
+        let aabb = aabb.translate(moved);
+        let (moved, collided) = move_and_collide(aabb, glam::vec3(0.0, 0.0, -7.5), wall);
+        assert_eq!(moved, glam::vec3(0.0, 0.0, -7.5));
+        assert!(!collided.any());
+
+        // Nothing is solid so moving away isn't blocked
+        let (moved, _) = move_and_collide(aabb, glam::vec3(4.0, 0.0, 0.0), wall);
+        assert_eq!(moved.x, 4.0);
+    }
+
+    #[test]
+    fn hits_ceiling() {
+        let ceiling = |pos: glam::IVec3| pos.y >= 4;
+        let aabb = PLAYER.translate(glam::vec3(-10.5, 0.0, -10.5));
+        let (moved, collided) = move_and_collide(aabb, glam::vec3(0.0, 5.0, 0.0), ceiling);
+        assert!((moved.y - 2.2).abs() < 1e-5);
+        assert!(collided.y);
     }
 }
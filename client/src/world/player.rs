@@ -1,11 +1,21 @@
-use super::{PhysicsBody, WorldTransform};
+use super::{Aabb, ChunkManager, PhysicsBody, WorldTransform};
 use crate::{input::Input, window::Window};
 use bevy_ecs::prelude::*;
+use opencuboids_common::{Biome, PLAYER_BOX_MAX, PLAYER_BOX_MIN};
 use winit::event::VirtualKeyCode;
 
 #[derive(Component)]
 pub struct Player;
 
+const SPEED: f32 = 10.0;
+const JUMP_SPEED: f32 = 9.0;
+
+/// The collision box around the eyes of the player
+pub const PLAYER_AABB: Aabb = Aabb {
+    min: PLAYER_BOX_MIN,
+    max: PLAYER_BOX_MAX,
+};
+
 pub fn player_movement(
     input: Res<Input>,
     mut query: Query<(&mut PhysicsBody, &mut WorldTransform, With<Player>)>,
@@ -13,7 +23,7 @@ pub fn player_movement(
     let (mut body, mut transform, _) = query.single_mut();
 
     const SENSITIVITY: f32 = 0.1;
-    let mut rotation = &mut transform.rotation;
+    let rotation = &mut transform.rotation;
     rotation.x -= input.mouse_offset.x * SENSITIVITY;
     rotation.y = f32::clamp(rotation.y - input.mouse_offset.y * SENSITIVITY, -89.0, 89.0);
 
@@ -37,15 +47,22 @@ pub fn player_movement(
         force -= left;
     }
 
-    // y movement
-    if input.is_key_pressed(VirtualKeyCode::Space) {
-        force += glam::Vec3::Y;
+    if input.is_key_just_pressed(VirtualKeyCode::F) {
+        body.flying = !body.flying;
     }
-    if input.is_key_pressed(VirtualKeyCode::LShift) {
-        force -= glam::Vec3::Y;
+
+    // y movement
+    if body.flying {
+        if input.is_key_pressed(VirtualKeyCode::Space) {
+            force += glam::Vec3::Y;
+        }
+        if input.is_key_pressed(VirtualKeyCode::LShift) {
+            force -= glam::Vec3::Y;
+        }
+    } else if body.grounded && input.is_key_pressed(VirtualKeyCode::Space) {
+        body.velocity.y = JUMP_SPEED;
     }
 
-    const SPEED: f32 = 10.0;
     body.force = force.normalize_or_zero() * SPEED;
 }
 
@@ -55,3 +72,20 @@ pub fn mouse_lock(mut state: ResMut<Window>, input: Res<Input>) {
         state.set_mouse_lock(!locked);
     }
 }
+
+/// Logs the biome whenever the player goes into a different one
+pub fn log_biome(
+    chunk_manager: Res<ChunkManager>,
+    mut current: Local<Option<Biome>>,
+    query: Query<&WorldTransform, With<Player>>,
+) {
+    let position = query.single().position.floor().as_ivec3();
+    let Some(biome) = chunk_manager.biome_at(position) else {
+        return;
+    };
+
+    if *current != Some(biome) {
+        log::info!("Entered the {:?} biome", biome);
+        *current = Some(biome);
+    }
+}
@@ -0,0 +1,170 @@
+use bevy_ecs::prelude::*;
+use bevy_utils::{Duration, HashMap, Instant};
+use opencuboids_common::network::EntityID;
+use std::collections::VecDeque;
+
+use super::{WorldTransform, PLAYER_AABB};
+
+/// How far behind remote players get shown so there's usually a snapshot on both sides to
+/// interpolate between
+const INTERPOLATION_DELAY: Duration = Duration::from_millis(100);
+
+struct Snapshot {
+    received: Instant,
+    position: glam::Vec3,
+    rotation: glam::Vec2,
+}
+
+/// Another player on the server, moved between the transforms received from it
+#[derive(Component)]
+pub struct RemotePlayer {
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl RemotePlayer {
+    pub fn push_snapshot(&mut self, received: Instant, position: glam::Vec3, rotation: glam::Vec2) {
+        self.snapshots.push_back(Snapshot {
+            received,
+            position,
+            rotation,
+        });
+    }
+
+    /// Gets the position and rotation at the time by interpolating between the snapshots around
+    /// it, staying at the first or last snapshot if it's outside of them
+    fn sample(&self, time: Instant) -> Option<(glam::Vec3, glam::Vec2)> {
+        let next_index = self
+            .snapshots
+            .iter()
+            .position(|snapshot| snapshot.received > time)
+            .unwrap_or(self.snapshots.len());
+
+        let next = match self.snapshots.get(next_index) {
+            Some(next) => next,
+            None => {
+                let last = self.snapshots.back()?;
+                return Some((last.position, last.rotation));
+            }
+        };
+
+        let previous = match next_index.checked_sub(1) {
+            Some(index) => &self.snapshots[index],
+            None => return Some((next.position, next.rotation)),
+        };
+
+        let t = (time - previous.received).as_secs_f32()
+            / (next.received - previous.received).as_secs_f32();
+        Some((
+            previous.position.lerp(next.position, t),
+            previous.rotation.lerp(next.rotation, t),
+        ))
+    }
+
+    /// Drops the snapshots that are too old to be interpolated from at the time
+    fn remove_old_snapshots(&mut self, time: Instant) {
+        while self.snapshots.len() > 1 && self.snapshots[1].received <= time {
+            self.snapshots.pop_front();
+        }
+    }
+}
+
+/// The entities of the remote players by their id on the server
+#[derive(Resource, Default)]
+pub struct RemotePlayers(HashMap<EntityID, Entity>);
+
+impl RemotePlayers {
+    pub fn get(&self, id: EntityID) -> Option<Entity> {
+        self.0.get(&id).copied()
+    }
+
+    pub fn spawn(
+        &mut self,
+        commands: &mut Commands,
+        id: EntityID,
+        name: String,
+        position: glam::Vec3,
+        rotation: glam::Vec2,
+    ) {
+        log::debug!("Spawning remote player {} with id {}", name, id);
+        let mut remote = RemotePlayer {
+            snapshots: VecDeque::new(),
+        };
+        remote.push_snapshot(Instant::now(), position, rotation);
+
+        let entity = commands
+            .spawn((WorldTransform { position, rotation }, PLAYER_AABB, remote))
+            .id();
+        if let Some(old_entity) = self.0.insert(id, entity) {
+            commands.entity(old_entity).despawn();
+        }
+    }
+
+    pub fn despawn(&mut self, commands: &mut Commands, id: EntityID) {
+        if let Some(entity) = self.0.remove(&id) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+pub fn interpolate_remote_players(
+    mut remote_query: Query<(&mut RemotePlayer, &mut WorldTransform)>,
+) {
+    let time = Instant::now() - INTERPOLATION_DELAY;
+    for (mut remote, mut transform) in remote_query.iter_mut() {
+        remote.remove_old_snapshots(time);
+        if let Some((position, rotation)) = remote.sample(time) {
+            transform.position = position;
+            transform.rotation = rotation;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_between_snapshots() {
+        let start = Instant::now();
+        let mut remote = RemotePlayer {
+            snapshots: VecDeque::new(),
+        };
+        assert_eq!(remote.sample(start), None);
+
+        let step = Duration::from_millis(50);
+        remote.push_snapshot(start, glam::Vec3::ZERO, glam::Vec2::ZERO);
+        remote.push_snapshot(
+            start + step,
+            glam::vec3(2.0, 0.0, 0.0),
+            glam::vec2(90.0, 0.0),
+        );
+        remote.push_snapshot(
+            start + step * 2,
+            glam::vec3(2.0, 4.0, 0.0),
+            glam::vec2(90.0, 0.0),
+        );
+
+        // Stays at the ends outside of the snapshots
+        assert_eq!(
+            remote.sample(start - step),
+            Some((glam::Vec3::ZERO, glam::Vec2::ZERO))
+        );
+        assert_eq!(
+            remote.sample(start + step * 3),
+            Some((glam::vec3(2.0, 4.0, 0.0), glam::vec2(90.0, 0.0)))
+        );
+
+        let (position, rotation) = remote.sample(start + step / 2).unwrap();
+        assert!(position.abs_diff_eq(glam::vec3(1.0, 0.0, 0.0), 1e-4));
+        assert!(rotation.abs_diff_eq(glam::vec2(45.0, 0.0), 1e-4));
+
+        let (position, _) = remote.sample(start + step + step / 4).unwrap();
+        assert!(position.abs_diff_eq(glam::vec3(2.0, 1.0, 0.0), 1e-4));
+
+        // Only the snapshot before the time is needed to keep interpolating
+        remote.remove_old_snapshots(start + step + step / 4);
+        assert_eq!(remote.snapshots.len(), 2);
+        let (position, _) = remote.sample(start + step + step / 4).unwrap();
+        assert!(position.abs_diff_eq(glam::vec3(2.0, 1.0, 0.0), 1e-4));
+    }
+}
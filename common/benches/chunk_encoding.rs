@@ -0,0 +1,49 @@
+//! Compares the encoded size of chunks with different block patterns
+//!
+//! Run with `cargo bench -p opencuboids-common`
+
+use opencuboids_common::{iter_3d, BlockID, Chunk, CHUNK_SIZE, CHUNK_VOLUME};
+
+fn chunk_from_fn(f: impl Fn(glam::IVec3) -> BlockID) -> Chunk {
+    let mut chunk = Chunk::new(glam::IVec3::ZERO);
+    for pos in iter_3d(0, CHUNK_SIZE as i32) {
+        chunk.set_block(pos.as_uvec3(), f(pos));
+    }
+    chunk
+}
+
+fn main() {
+    let chunks = [
+        ("empty", chunk_from_fn(|_| 0)),
+        ("full", chunk_from_fn(|_| 1)),
+        (
+            "terrain",
+            chunk_from_fn(|pos| {
+                let height = (f32::sin(pos.x as f32 / 10.0) * f32::cos(pos.z as f32 / 10.0) * 10.0
+                    + 16.0) as i32;
+                (pos.y < height) as BlockID
+            }),
+        ),
+        (
+            "checkerboard",
+            chunk_from_fn(|pos| ((pos.x + pos.y + pos.z) % 2) as BlockID),
+        ),
+    ];
+
+    println!(
+        "{:<14}{:>10}{:>10}{:>12}",
+        "chunk", "raw", "encoded", "encode time"
+    );
+    for (name, chunk) in chunks {
+        let start = std::time::Instant::now();
+        let data = bincode::serialize(&chunk).unwrap();
+        let elapsed = start.elapsed();
+        println!(
+            "{:<14}{:>10}{:>10}{:>12?}",
+            name,
+            CHUNK_VOLUME * std::mem::size_of::<BlockID>(),
+            data.len(),
+            elapsed
+        );
+    }
+}
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+/// The kind of area a part of the world is, chosen by the world generation from the temperature
+/// and humidity
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Biome {
+    Plains,
+    Forest,
+    Desert,
+    Tundra,
+    Mountains,
+}
+
+impl Biome {
+    pub const ALL: [Biome; 5] = [
+        Self::Plains,
+        Self::Forest,
+        Self::Desert,
+        Self::Tundra,
+        Self::Mountains,
+    ];
+}
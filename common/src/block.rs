@@ -0,0 +1,257 @@
+use serde::{Deserialize, Serialize};
+
+use crate::BlockID;
+
+/// Id of the block that fills empty space, always has to be defined in the registry
+pub const AIR: BlockID = 0;
+
+/// The names of the textures used on each side of a block
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FaceTextures {
+    All(String),
+    TopBottomSide {
+        top: String,
+        bottom: String,
+        side: String,
+    },
+    /// Uses a direction index
+    Faces([String; 6]),
+}
+
+impl FaceTextures {
+    pub fn get(&self, dir_index: usize) -> &str {
+        match self {
+            Self::All(texture) => texture,
+            Self::TopBottomSide { top, bottom, side } => match dir_index {
+                4 => top,
+                5 => bottom,
+                _ => side,
+            },
+            Self::Faces(textures) => &textures[dir_index],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BlockDefinition {
+    pub id: BlockID,
+    pub name: String,
+    /// Whether entities collide with the block
+    pub solid: bool,
+    /// Whether the faces of blocks behind this block can be seen
+    pub transparent: bool,
+    /// The block isn't rendered if there's no textures
+    #[serde(default)]
+    pub textures: Option<FaceTextures>,
+    /// Light level from 0 to 15 that the block emits
+    #[serde(default)]
+    pub light_emission: u8,
+    /// Whether the block falls down when there's nothing under it
+    #[serde(default)]
+    pub falls: bool,
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Io(std::io::Error),
+    Parse(String),
+    DuplicateId(BlockID),
+    DuplicateName(String),
+    InvalidAir,
+    InvalidLightEmission(String),
+}
+
+impl std::fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Parse(err) => write!(f, "invalid block definitions - {}", err),
+            Self::DuplicateId(id) => write!(f, "block id {} is defined more than once", id),
+            Self::DuplicateName(name) => write!(f, "block {} is defined more than once", name),
+            Self::InvalidAir => write!(
+                f,
+                "block id {} has to be a non solid, transparent block without textures",
+                AIR
+            ),
+            Self::InvalidLightEmission(name) => {
+                write!(f, "light emission of block {} is more than 15", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+/// Every block type in the world, loaded from a RON file of block definitions
+///
+/// The server sends its registry to clients when they connect so both sides agree on what the
+/// ids mean.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "Vec<BlockDefinition>", into = "Vec<BlockDefinition>")]
+pub struct BlockRegistry {
+    definitions: Vec<BlockDefinition>,
+    /// Maps a block id to an index into definitions
+    id_to_index: Vec<Option<usize>>,
+}
+
+impl BlockRegistry {
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, RegistryError> {
+        let source = std::fs::read_to_string(path).map_err(RegistryError::Io)?;
+        Self::from_ron(&source)
+    }
+
+    pub fn from_ron(source: &str) -> Result<Self, RegistryError> {
+        let definitions = ron::from_str::<Vec<BlockDefinition>>(source)
+            .map_err(|err| RegistryError::Parse(err.to_string()))?;
+        Self::try_from(definitions)
+    }
+
+    /// Gets the block definition of the id, unknown ids are treated like air
+    pub fn get(&self, id: BlockID) -> &BlockDefinition {
+        let index = self
+            .id_to_index
+            .get(id as usize)
+            .copied()
+            .flatten()
+            .unwrap_or(self.id_to_index[AIR as usize].unwrap());
+        &self.definitions[index]
+    }
+
+    pub fn id(&self, name: &str) -> Option<BlockID> {
+        self.definitions
+            .iter()
+            .find(|definition| definition.name == name)
+            .map(|definition| definition.id)
+    }
+
+    pub fn is_solid(&self, id: BlockID) -> bool {
+        self.get(id).solid
+    }
+
+    pub fn is_transparent(&self, id: BlockID) -> bool {
+        self.get(id).transparent
+    }
+
+    pub fn falls(&self, id: BlockID) -> bool {
+        self.get(id).falls
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &BlockDefinition> {
+        self.definitions.iter()
+    }
+
+    /// FNV-1a hash of the encoded registry, stays the same across runs and platforms
+    pub fn checksum(&self) -> u64 {
+        let data = bincode::serialize(self).unwrap();
+        data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+}
+
+impl Default for BlockRegistry {
+    fn default() -> Self {
+        Self::from_ron(include_str!("../../assets/blocks.ron"))
+            .expect("Built in block definitions are invalid")
+    }
+}
+
+impl TryFrom<Vec<BlockDefinition>> for BlockRegistry {
+    type Error = RegistryError;
+
+    fn try_from(definitions: Vec<BlockDefinition>) -> Result<Self, Self::Error> {
+        let mut id_to_index = Vec::new();
+        for (i, definition) in definitions.iter().enumerate() {
+            let id = definition.id as usize;
+            if id >= id_to_index.len() {
+                id_to_index.resize(id + 1, None);
+            }
+
+            if id_to_index[id].replace(i).is_some() {
+                return Err(RegistryError::DuplicateId(definition.id));
+            }
+
+            if definitions[..i]
+                .iter()
+                .any(|other| other.name == definition.name)
+            {
+                return Err(RegistryError::DuplicateName(definition.name.clone()));
+            }
+
+            if definition.light_emission > 15 {
+                return Err(RegistryError::InvalidLightEmission(definition.name.clone()));
+            }
+        }
+
+        let air = id_to_index
+            .get(AIR as usize)
+            .copied()
+            .flatten()
+            .map(|i| &definitions[i]);
+        match air {
+            Some(air) if !air.solid && air.transparent && air.textures.is_none() => (),
+            _ => return Err(RegistryError::InvalidAir),
+        }
+
+        Ok(Self {
+            definitions,
+            id_to_index,
+        })
+    }
+}
+
+impl From<BlockRegistry> for Vec<BlockDefinition> {
+    fn from(registry: BlockRegistry) -> Self {
+        registry.definitions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_blocks() {
+        let registry = BlockRegistry::default();
+        assert!(!registry.is_solid(AIR));
+        assert!(registry.is_transparent(AIR));
+        assert!(registry.falls(registry.id("sand").unwrap()));
+
+        let grass = registry.get(registry.id("grass").unwrap());
+        let textures = grass.textures.as_ref().unwrap();
+        assert_eq!(textures.get(4), "grass_top");
+        assert_eq!(textures.get(0), "grass_side");
+
+        // Unknown ids act like air
+        assert_eq!(registry.get(BlockID::MAX).name, "air");
+
+        let data = bincode::serialize(&registry).unwrap();
+        assert_eq!(
+            bincode::deserialize::<BlockRegistry>(&data).unwrap(),
+            registry
+        );
+    }
+
+    #[test]
+    fn invalid_definitions() {
+        let duplicate_id = r#"[
+            (id: 0, name: "air", solid: false, transparent: true),
+            (id: 0, name: "stone", solid: true, transparent: false),
+        ]"#;
+        assert!(matches!(
+            BlockRegistry::from_ron(duplicate_id),
+            Err(RegistryError::DuplicateId(0))
+        ));
+
+        let no_air = r#"[(id: 1, name: "stone", solid: true, transparent: false)]"#;
+        assert!(matches!(
+            BlockRegistry::from_ron(no_air),
+            Err(RegistryError::InvalidAir)
+        ));
+
+        assert!(matches!(
+            BlockRegistry::from_ron("[(id: 0)]"),
+            Err(RegistryError::Parse(_))
+        ));
+    }
+}
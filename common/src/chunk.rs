@@ -1,21 +1,25 @@
-use serde::{
-    de::{SeqAccess, Visitor},
-    ser::{SerializeTuple, Serializer},
-    Deserialize, Deserializer, Serialize,
-};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{palette::PalettedStorage, Light};
 
 pub const CHUNK_SIZE: usize = 32;
 pub const CHUNK_VOLUME: usize = CHUNK_SIZE.pow(3);
-pub type BlockID = u8;
+pub type BlockID = u16;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Chunk {
     #[serde(
         serialize_with = "serialize_blocks",
         deserialize_with = "deserialize_blocks"
     )]
-    pub blocks: [BlockID; CHUNK_VOLUME],
+    blocks: PalettedStorage,
     pub pos: glam::IVec3,
+    /// Stores the packed light of each block like the block ids
+    #[serde(
+        serialize_with = "serialize_blocks",
+        deserialize_with = "deserialize_blocks"
+    )]
+    light: PalettedStorage,
 }
 
 impl std::fmt::Debug for Chunk {
@@ -27,19 +31,20 @@ impl std::fmt::Debug for Chunk {
 impl Chunk {
     pub fn new(pos: glam::IVec3) -> Self {
         Self {
-            blocks: [0; CHUNK_VOLUME],
+            blocks: PalettedStorage::filled(0),
             pos,
+            light: PalettedStorage::filled(0),
         }
     }
 }
 
 impl Chunk {
     pub fn set_block(&mut self, pos: glam::UVec3, id: BlockID) {
-        self.blocks[pos_to_index(pos)] = id;
+        self.blocks.set(pos_to_index(pos), id);
     }
 
     pub fn get_block(&self, pos: glam::UVec3) -> BlockID {
-        self.blocks[pos_to_index(pos)]
+        self.blocks.get(pos_to_index(pos))
     }
 
     pub fn try_get_block(&self, pos: glam::UVec3) -> Option<BlockID> {
@@ -49,6 +54,19 @@ impl Chunk {
             Some(self.get_block(pos))
         }
     }
+
+    pub fn get_light(&self, pos: glam::UVec3) -> Light {
+        Light::from_bits(self.light.get(pos_to_index(pos)))
+    }
+
+    pub fn set_light(&mut self, pos: glam::UVec3, light: Light) {
+        self.light.set(pos_to_index(pos), light.to_bits());
+    }
+
+    /// Returns the id if the entire chunk is made of the same block
+    pub fn single_block(&self) -> Option<BlockID> {
+        self.blocks.single_value()
+    }
 }
 
 pub fn iter_3d(start: i32, end: i32) -> impl Iterator<Item = glam::IVec3> {
@@ -63,45 +81,76 @@ pub fn iter_3d_vec(start: glam::IVec3, end: glam::IVec3) -> impl Iterator<Item =
     })
 }
 
+/// Amount of the same block repeated in a row, can hold a run of an entire chunk
+type RunLength = u16;
+
+#[derive(Serialize, Deserialize)]
+enum EncodedBlocks {
+    Raw(Vec<BlockID>),
+    /// Run length encoded (length, id) pairs
+    Runs(Vec<(RunLength, BlockID)>),
+}
+
+/// Serializes the blocks run length encoded unless that would be larger than the raw blocks
 pub fn serialize_blocks<S: Serializer>(
-    blocks: &[BlockID; CHUNK_VOLUME],
+    blocks: &PalettedStorage,
     serializer: S,
 ) -> Result<S::Ok, S::Error> {
-    let mut seq = serializer.serialize_tuple(blocks.len())?;
-    // TODO: add run length encoding
-    for block in blocks {
-        seq.serialize_element(block)?
+    let mut runs: Vec<(RunLength, BlockID)> = Vec::new();
+    for block in blocks.iter() {
+        match runs.last_mut() {
+            Some((length, id)) if *id == block => *length += 1,
+            _ => runs.push((1, block)),
+        }
     }
-    seq.end()
+
+    let runs_size =
+        runs.len() * (std::mem::size_of::<RunLength>() + std::mem::size_of::<BlockID>());
+    let encoded = if runs_size < CHUNK_VOLUME * std::mem::size_of::<BlockID>() {
+        EncodedBlocks::Runs(runs)
+    } else {
+        EncodedBlocks::Raw(blocks.iter().collect())
+    };
+    encoded.serialize(serializer)
 }
 
 pub fn deserialize_blocks<'a, D: Deserializer<'a>>(
     deserializer: D,
-) -> Result<[BlockID; CHUNK_VOLUME], D::Error> {
-    struct ArrayVisitor;
+) -> Result<PalettedStorage, D::Error> {
+    use serde::de::Error;
 
-    impl<'de> Visitor<'de> for ArrayVisitor {
-        type Value = [BlockID; CHUNK_VOLUME];
+    match EncodedBlocks::deserialize(deserializer)? {
+        EncodedBlocks::Raw(raw) => {
+            if raw.len() != CHUNK_VOLUME {
+                return Err(D::Error::invalid_length(raw.len(), &"blocks in a chunk"));
+            }
 
-        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-            formatter.write_str("array of blocks ids in a chunk")
+            let mut blocks = PalettedStorage::filled(raw[0]);
+            for (i, id) in raw.into_iter().enumerate() {
+                blocks.set(i, id);
+            }
+            Ok(blocks)
         }
+        EncodedBlocks::Runs(runs) => {
+            // Start with the first id so a chunk of one block stays as a single value
+            let mut blocks = PalettedStorage::filled(runs.first().map_or(0, |run| run.1));
+            let mut i = 0;
+            for (length, id) in runs {
+                let end = i + length as usize;
+                if length == 0 || end > CHUNK_VOLUME {
+                    return Err(D::Error::invalid_length(end, &"runs covering a chunk"));
+                }
+
+                blocks.set_range(i..end, id);
+                i = end;
+            }
 
-        fn visit_seq<A>(self, mut seq: A) -> Result<[BlockID; CHUNK_VOLUME], A::Error>
-        where
-            A: SeqAccess<'de>,
-        {
-            let mut blocks = [0; CHUNK_VOLUME];
-            for i in 0..CHUNK_VOLUME {
-                blocks[i] = seq
-                    .next_element()?
-                    .ok_or_else(|| serde::de::Error::invalid_length(i, &self))?;
+            if i != CHUNK_VOLUME {
+                return Err(D::Error::invalid_length(i, &"runs covering a chunk"));
             }
             Ok(blocks)
         }
     }
-
-    deserializer.deserialize_tuple(CHUNK_VOLUME, ArrayVisitor)
 }
 
 fn pos_to_index(block_pos: glam::UVec3) -> usize {
@@ -120,6 +169,26 @@ pub fn in_bounds(chunk_pos: glam::IVec3, center: glam::IVec3, distance: i32) ->
         && pos.z < distance
 }
 
+/// Gets the position of the chunk that a world block position is in
+pub fn block_to_chunk_pos(block_pos: glam::IVec3) -> glam::IVec3 {
+    let size = CHUNK_SIZE as i32;
+    glam::ivec3(
+        block_pos.x.div_euclid(size),
+        block_pos.y.div_euclid(size),
+        block_pos.z.div_euclid(size),
+    )
+}
+
+/// Gets the position of a world block position inside of its chunk
+pub fn block_to_local_pos(block_pos: glam::IVec3) -> glam::UVec3 {
+    let size = CHUNK_SIZE as i32;
+    glam::uvec3(
+        block_pos.x.rem_euclid(size) as u32,
+        block_pos.y.rem_euclid(size) as u32,
+        block_pos.z.rem_euclid(size) as u32,
+    )
+}
+
 /// Uses a direction index
 pub const DIRECTION_TO_VECTOR: &[glam::IVec3] = &[
     glam::ivec3(0, 0, 1),  // North
@@ -129,3 +198,91 @@ pub const DIRECTION_TO_VECTOR: &[glam::IVec3] = &[
     glam::ivec3(0, 1, 0),  // Top
     glam::ivec3(0, -1, 0), // Bottom
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk_from_fn(f: impl Fn(glam::IVec3) -> BlockID) -> Chunk {
+        let mut chunk = Chunk::new(glam::ivec3(1, -2, 3));
+        for pos in iter_3d(0, CHUNK_SIZE as i32) {
+            chunk.set_block(pos.as_uvec3(), f(pos));
+        }
+        chunk
+    }
+
+    fn assert_round_trip(chunk: &Chunk) -> usize {
+        let data = bincode::serialize(chunk).unwrap();
+        let decoded = bincode::deserialize::<Chunk>(&data).unwrap();
+        assert_eq!(decoded.pos, chunk.pos);
+        assert!(decoded.blocks.iter().eq(chunk.blocks.iter()));
+        assert!(decoded.light.iter().eq(chunk.light.iter()));
+        data.len()
+    }
+
+    #[test]
+    fn round_trip() {
+        let empty = assert_round_trip(&chunk_from_fn(|_| 0));
+        let full = assert_round_trip(&chunk_from_fn(|_| 1));
+        let terrain = assert_round_trip(&chunk_from_fn(|pos| (pos.y < pos.x / 2 + 8) as BlockID));
+        let checkerboard = assert_round_trip(&chunk_from_fn(|pos| {
+            ((pos.x + pos.y + pos.z) % 2) as BlockID
+        }));
+        assert_round_trip(&chunk_from_fn(|pos| {
+            (pos.x * 31 + pos.y * 17 + pos.z) as BlockID
+        }));
+
+        // The blocks and the light are a single run each
+        assert!(empty < 48 && full < 48);
+        assert!(terrain < CHUNK_VOLUME / 4);
+        // Falls back to the raw blocks when nothing repeats
+        assert!(checkerboard <= CHUNK_VOLUME * 2 + 48);
+    }
+
+    #[test]
+    fn palette_storage() {
+        let mut chunk = Chunk::new(glam::IVec3::ZERO);
+        assert_eq!(chunk.single_block(), Some(0));
+
+        // More ids than fit in a u8
+        let id_at = |pos: glam::IVec3| (pos.x * 100 + pos.z) as BlockID;
+        for pos in iter_3d(0, CHUNK_SIZE as i32) {
+            if pos.y == 3 {
+                chunk.set_block(pos.as_uvec3(), id_at(pos));
+            }
+        }
+
+        assert_eq!(chunk.single_block(), None);
+        for pos in iter_3d(0, CHUNK_SIZE as i32) {
+            let expected = if pos.y == 3 { id_at(pos) } else { 0 };
+            assert_eq!(chunk.get_block(pos.as_uvec3()), expected);
+        }
+        assert_round_trip(&chunk);
+
+        let stone = chunk_from_fn(|_| 1);
+        assert_eq!(stone.single_block(), Some(1));
+        let data = bincode::serialize(&stone).unwrap();
+        let decoded = bincode::deserialize::<Chunk>(&data).unwrap();
+        assert_eq!(decoded.single_block(), Some(1));
+    }
+
+    #[test]
+    fn rejects_invalid_runs() {
+        let pos = glam::IVec3::ZERO;
+        let too_short = EncodedBlocks::Runs(vec![(10, 1)]);
+        let too_short = bincode::serialize(&(too_short, pos)).unwrap();
+        assert!(bincode::deserialize::<Chunk>(&too_short).is_err());
+
+        let too_long = EncodedBlocks::Runs(vec![(CHUNK_VOLUME as RunLength, 0), (1, 1)]);
+        let too_long = bincode::serialize(&(too_long, pos)).unwrap();
+        assert!(bincode::deserialize::<Chunk>(&too_long).is_err());
+
+        let empty_run = EncodedBlocks::Runs(vec![(0, 1), (CHUNK_VOLUME as RunLength, 0)]);
+        let empty_run = bincode::serialize(&(empty_run, pos)).unwrap();
+        assert!(bincode::deserialize::<Chunk>(&empty_run).is_err());
+
+        let wrong_size = EncodedBlocks::Raw(vec![0; CHUNK_VOLUME - 1]);
+        let wrong_size = bincode::serialize(&(wrong_size, pos)).unwrap();
+        assert!(bincode::deserialize::<Chunk>(&wrong_size).is_err());
+    }
+}
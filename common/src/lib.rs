@@ -1,10 +1,27 @@
+mod biome;
+mod block;
 mod chunk;
+mod light;
 pub mod network;
+mod palette;
+mod raycast;
 
+pub use biome::*;
+pub use block::*;
 pub use chunk::*;
+pub use light::*;
+pub use raycast::*;
 
 pub const DEFAULT_PORT: u16 = 29707;
 
+/// Corners of the collision box around the eyes of the player, the server checks moves
+/// against the same box the client collides with
+pub const PLAYER_BOX_MIN: glam::Vec3 = glam::vec3(-0.3, -1.6, -0.3);
+pub const PLAYER_BOX_MAX: glam::Vec3 = glam::vec3(0.3, 0.2, 0.3);
+/// Fastest players fall in blocks per second, walking while falling has to stay under the
+/// server's speed limit
+pub const MAX_FALL_SPEED: f32 = 30.0;
+
 pub fn log_setup() {
     env_logger::Builder::default()
         .filter_level(log::LevelFilter::Warn)
@@ -0,0 +1,545 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block_to_chunk_pos, block_to_local_pos, iter_3d, BlockID, BlockRegistry, Chunk, CHUNK_SIZE,
+    CHUNK_VOLUME, DIRECTION_TO_VECTOR,
+};
+
+pub const MAX_LIGHT: u8 = 15;
+
+/// Direction index of going down
+const DOWN: usize = 5;
+
+/// Sky light and block light of a block, each from 0 to MAX_LIGHT
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Light(u8);
+
+/// The new light of the blocks that had their light changed grouped by the position of their
+/// chunk
+pub type LightChanges = HashMap<glam::IVec3, HashMap<glam::IVec3, Light>>;
+
+impl Light {
+    /// Full sky light and no block light
+    pub const SKY: Self = Self(MAX_LIGHT << 4);
+
+    pub fn new(sky: u8, block: u8) -> Self {
+        Self(sky.min(MAX_LIGHT) << 4 | block.min(MAX_LIGHT))
+    }
+
+    pub fn sky(self) -> u8 {
+        self.0 >> 4
+    }
+
+    pub fn block(self) -> u8 {
+        self.0 & 0xf
+    }
+
+    pub(crate) fn from_bits(bits: u16) -> Self {
+        Self(bits as u8)
+    }
+
+    pub(crate) fn to_bits(self) -> u16 {
+        self.0 as u16
+    }
+
+    fn get(self, channel: Channel) -> u8 {
+        match channel {
+            Channel::Sky => self.sky(),
+            Channel::Block => self.block(),
+        }
+    }
+
+    fn with(self, channel: Channel, level: u8) -> Self {
+        match channel {
+            Channel::Sky => Self::new(level, self.block()),
+            Channel::Block => Self::new(self.sky(), level),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Channel {
+    Sky,
+    Block,
+}
+
+/// The chunks light can spread through, light doesn't go into chunks that aren't there
+pub trait ChunkAccess {
+    fn chunk(&self, chunk_pos: glam::IVec3) -> Option<&Chunk>;
+    fn chunk_mut(&mut self, chunk_pos: glam::IVec3) -> Option<&mut Chunk>;
+}
+
+impl<S: std::hash::BuildHasher> ChunkAccess for HashMap<glam::IVec3, Chunk, S> {
+    fn chunk(&self, chunk_pos: glam::IVec3) -> Option<&Chunk> {
+        self.get(&chunk_pos)
+    }
+
+    fn chunk_mut(&mut self, chunk_pos: glam::IVec3) -> Option<&mut Chunk> {
+        self.get_mut(&chunk_pos)
+    }
+}
+
+/// Lights a chunk that was just added to the world and spreads light between it and the chunks
+/// around it, the chunk below gets updated as well since it was lit as if it was under open sky
+///
+/// Returns the light changes of the other chunks, the chunk itself has been lit entirely.
+pub fn light_chunk(
+    world: &mut impl ChunkAccess,
+    blocks: &BlockRegistry,
+    chunk_pos: glam::IVec3,
+) -> LightChanges {
+    let size = CHUNK_SIZE as i32;
+    let Some(chunk) = world.chunk(chunk_pos) else {
+        return LightChanges::new();
+    };
+
+    // Lighting the chunk by itself first is much faster than going through the world
+    let lights = light_alone(chunk, world.chunk(chunk_pos + glam::IVec3::Y), blocks);
+    let chunk = world.chunk_mut(chunk_pos).unwrap();
+    for (pos, light) in iter_3d(0, size).zip(&lights) {
+        chunk.set_light(pos.as_uvec3(), *light);
+    }
+
+    let mut lighter = Lighter::new(world, blocks);
+
+    // Spread light both ways across the faces of the chunk
+    let start = chunk_pos * size;
+    let borders = iter_3d(-1, size + 1).filter(|pos| {
+        let outside = (pos.cmplt(glam::IVec3::ZERO) | pos.cmpge(glam::IVec3::splat(size)))
+            .bitmask()
+            .count_ones();
+        let on_edge =
+            (pos.cmpeq(glam::IVec3::ZERO) | pos.cmpeq(glam::IVec3::splat(size - 1))).any();
+        outside == 1 || (outside == 0 && on_edge)
+    });
+    let borders = borders.map(|pos| start + pos).collect::<VecDeque<_>>();
+    for channel in [Channel::Sky, Channel::Block] {
+        lighter.spread(borders.clone(), channel);
+    }
+
+    // Only the columns the chunk blocks sky light from need updating
+    let below = iter_3d(0, size)
+        .filter(|pos| pos.y == 0 && lights[light_index(*pos)].sky() != MAX_LIGHT)
+        .map(|pos| start + pos - glam::IVec3::Y)
+        .collect::<Vec<_>>();
+    lighter.update_channel(&below, Channel::Sky);
+
+    let mut changes = lighter.changes();
+    changes.remove(&chunk_pos);
+    changes
+}
+
+/// Recalculates the light at the positions after the blocks there have changed and spreads the
+/// change to everything around them, including into neighbouring chunks
+///
+/// Blocks under a chunk that isn't there get full sky light. Returns the light changes, the
+/// changes stop at the chunks that aren't there.
+pub fn update_light(
+    world: &mut impl ChunkAccess,
+    blocks: &BlockRegistry,
+    positions: impl IntoIterator<Item = glam::IVec3>,
+) -> LightChanges {
+    let mut lighter = Lighter::new(world, blocks);
+    let positions = positions.into_iter().collect::<Vec<_>>();
+    for channel in [Channel::Sky, Channel::Block] {
+        lighter.update_channel(&positions, channel);
+    }
+    lighter.changes()
+}
+
+/// Gets the level that light going from a block in the direction has when it gets to the next
+/// block, full sky light goes straight down forever
+fn spread_level(level: u8, channel: Channel, dir_index: usize) -> u8 {
+    if channel == Channel::Sky && dir_index == DOWN && level == MAX_LIGHT {
+        MAX_LIGHT
+    } else {
+        level.saturating_sub(1)
+    }
+}
+
+/// Index of a position in the chunk in the order of iter_3d
+fn light_index(pos: glam::IVec3) -> usize {
+    let size = CHUNK_SIZE as i32;
+    ((pos.x * size + pos.y) * size + pos.z) as usize
+}
+
+/// Lights a chunk without the chunks around it except for the sky light coming down from the
+/// chunk above, returns the light of every block in the order of iter_3d
+fn light_alone(chunk: &Chunk, above: Option<&Chunk>, blocks: &BlockRegistry) -> Vec<Light> {
+    let size = CHUNK_SIZE as i32;
+    let transparent = iter_3d(0, size)
+        .map(|pos| blocks.is_transparent(chunk.get_block(pos.as_uvec3())))
+        .collect::<Vec<_>>();
+    let mut lights = vec![Light::default(); CHUNK_VOLUME];
+
+    for channel in [Channel::Sky, Channel::Block] {
+        let mut queue = VecDeque::new();
+        for pos in iter_3d(0, size) {
+            let index = light_index(pos);
+            let level = match channel {
+                Channel::Block => blocks.get(chunk.get_block(pos.as_uvec3())).light_emission,
+                Channel::Sky if pos.y == size - 1 && transparent[index] => {
+                    above.map_or(MAX_LIGHT, |above| {
+                        let above_light =
+                            above.get_light(glam::uvec3(pos.x as u32, 0, pos.z as u32));
+                        spread_level(above_light.sky(), channel, DOWN)
+                    })
+                }
+                Channel::Sky => 0,
+            };
+
+            if level > 0 {
+                lights[index] = lights[index].with(channel, level);
+                queue.push_back(pos);
+            }
+        }
+
+        while let Some(pos) = queue.pop_front() {
+            let level = lights[light_index(pos)].get(channel);
+            for (dir_index, dir_vec) in DIRECTION_TO_VECTOR.iter().enumerate() {
+                let neighbour_pos = pos + *dir_vec;
+                if neighbour_pos.cmplt(glam::IVec3::ZERO).any()
+                    || neighbour_pos.cmpge(glam::IVec3::splat(size)).any()
+                {
+                    continue;
+                }
+
+                let index = light_index(neighbour_pos);
+                let spread_level = spread_level(level, channel, dir_index);
+                if transparent[index] && lights[index].get(channel) < spread_level {
+                    lights[index] = lights[index].with(channel, spread_level);
+                    queue.push_back(neighbour_pos);
+                }
+            }
+        }
+    }
+
+    lights
+}
+
+struct Lighter<'a, W> {
+    world: &'a mut W,
+    blocks: &'a BlockRegistry,
+    /// Light of the blocks from before they were first changed
+    old_lights: HashMap<glam::IVec3, Light>,
+}
+
+impl<'a, W: ChunkAccess> Lighter<'a, W> {
+    fn new(world: &'a mut W, blocks: &'a BlockRegistry) -> Self {
+        Self {
+            world,
+            blocks,
+            old_lights: HashMap::new(),
+        }
+    }
+
+    /// Gets the blocks that ended up with different light, light often gets taken away then
+    /// spread back the same as it was
+    fn changes(self) -> LightChanges {
+        let mut changes = LightChanges::new();
+        for (pos, old_light) in &self.old_lights {
+            let (_, light) = self.get(*pos).unwrap();
+            if light != *old_light {
+                changes
+                    .entry(block_to_chunk_pos(*pos))
+                    .or_default()
+                    .insert(*pos, light);
+            }
+        }
+        changes
+    }
+
+    fn get(&self, pos: glam::IVec3) -> Option<(BlockID, Light)> {
+        self.world.chunk(block_to_chunk_pos(pos)).map(|chunk| {
+            let local_pos = block_to_local_pos(pos);
+            (chunk.get_block(local_pos), chunk.get_light(local_pos))
+        })
+    }
+
+    fn get_level(&self, pos: glam::IVec3, channel: Channel) -> Option<u8> {
+        self.get(pos).map(|(_, light)| light.get(channel))
+    }
+
+    fn set_level(&mut self, pos: glam::IVec3, channel: Channel, level: u8) {
+        let chunk_pos = block_to_chunk_pos(pos);
+        if let Some(chunk) = self.world.chunk_mut(chunk_pos) {
+            let local_pos = block_to_local_pos(pos);
+            let light = chunk.get_light(local_pos);
+            chunk.set_light(local_pos, light.with(channel, level));
+            self.old_lights.entry(pos).or_insert(light);
+        }
+    }
+
+    /// Gets the light the block at the position gives off by itself
+    fn source_level(&self, pos: glam::IVec3, id: BlockID, channel: Channel) -> u8 {
+        match channel {
+            Channel::Block => self.blocks.get(id).light_emission,
+            Channel::Sky => {
+                let open_sky = self
+                    .world
+                    .chunk(block_to_chunk_pos(pos + glam::IVec3::Y))
+                    .is_none();
+                if open_sky && self.blocks.is_transparent(id) {
+                    MAX_LIGHT
+                } else {
+                    0
+                }
+            }
+        }
+    }
+
+    fn update_channel(&mut self, positions: &[glam::IVec3], channel: Channel) {
+        let mut removal_queue = VecDeque::new();
+        let mut add_queue = VecDeque::new();
+
+        // Take away the old light and all the light that came from it, light that came from
+        // somewhere else gets spread again afterwards
+        for pos in positions {
+            let level = self.get_level(*pos, channel).unwrap_or(0);
+            if level > 0 {
+                self.set_level(*pos, channel, 0);
+                removal_queue.push_back((*pos, level));
+            }
+        }
+
+        while let Some((pos, level)) = removal_queue.pop_front() {
+            for (dir_index, dir_vec) in DIRECTION_TO_VECTOR.iter().enumerate() {
+                let neighbour_pos = pos + *dir_vec;
+                let neighbour_level = match self.get_level(neighbour_pos, channel) {
+                    Some(0) | None => continue,
+                    Some(neighbour_level) => neighbour_level,
+                };
+
+                if neighbour_level < level
+                    || spread_level(level, channel, dir_index) == neighbour_level
+                {
+                    self.set_level(neighbour_pos, channel, 0);
+                    removal_queue.push_back((neighbour_pos, neighbour_level));
+                } else {
+                    add_queue.push_back(neighbour_pos);
+                }
+            }
+        }
+
+        // Light the positions by themselves and from the light around them
+        for pos in positions {
+            let Some((id, light)) = self.get(*pos) else {
+                continue;
+            };
+
+            let mut level = self.source_level(*pos, id, channel);
+            if self.blocks.is_transparent(id) {
+                for (dir_index, dir_vec) in DIRECTION_TO_VECTOR.iter().enumerate() {
+                    // Light going in the direction comes from the opposite side
+                    if let Some(neighbour_level) = self.get_level(*pos - *dir_vec, channel) {
+                        level = level.max(spread_level(neighbour_level, channel, dir_index));
+                    }
+                }
+            }
+
+            if level > light.get(channel) {
+                self.set_level(*pos, channel, level);
+                add_queue.push_back(*pos);
+            }
+        }
+
+        self.spread(add_queue, channel);
+    }
+
+    /// Spreads the light of the positions in the queue to everything around them it can reach
+    fn spread(&mut self, mut queue: VecDeque<glam::IVec3>, channel: Channel) {
+        while let Some(pos) = queue.pop_front() {
+            let level = match self.get_level(pos, channel) {
+                Some(0) | None => continue,
+                Some(level) => level,
+            };
+
+            for (dir_index, dir_vec) in DIRECTION_TO_VECTOR.iter().enumerate() {
+                let neighbour_pos = pos + *dir_vec;
+                let spread_level = spread_level(level, channel, dir_index);
+                let Some((id, light)) = self.get(neighbour_pos) else {
+                    continue;
+                };
+
+                if self.blocks.is_transparent(id) && light.get(channel) < spread_level {
+                    self.set_level(neighbour_pos, channel, spread_level);
+                    queue.push_back(neighbour_pos);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AIR;
+
+    fn get_light(world: &HashMap<glam::IVec3, Chunk>, pos: glam::IVec3) -> Light {
+        world[&block_to_chunk_pos(pos)].get_light(block_to_local_pos(pos))
+    }
+
+    fn set_block(
+        world: &mut HashMap<glam::IVec3, Chunk>,
+        blocks: &BlockRegistry,
+        pos: glam::IVec3,
+        id: BlockID,
+    ) -> LightChanges {
+        world
+            .get_mut(&block_to_chunk_pos(pos))
+            .unwrap()
+            .set_block(block_to_local_pos(pos), id);
+        update_light(world, blocks, [pos])
+    }
+
+    /// Two chunks on top of each other with a stone floor at the bottom of the lower one
+    fn floor_world(blocks: &BlockRegistry) -> HashMap<glam::IVec3, Chunk> {
+        let stone = blocks.id("stone").unwrap();
+        let mut world = HashMap::new();
+        for chunk_pos in [glam::ivec3(0, 1, 0), glam::IVec3::ZERO] {
+            let mut chunk = Chunk::new(chunk_pos);
+            if chunk_pos.y == 0 {
+                for pos in iter_3d(0, CHUNK_SIZE as i32).filter(|pos| pos.y == 0) {
+                    chunk.set_block(pos.as_uvec3(), stone);
+                }
+            }
+            world.insert(chunk_pos, chunk);
+            light_chunk(&mut world, blocks, chunk_pos);
+        }
+        world
+    }
+
+    #[test]
+    fn sky_light() {
+        let blocks = BlockRegistry::default();
+        let stone = blocks.id("stone").unwrap();
+        let mut world = floor_world(&blocks);
+
+        // Sky light goes straight down through both chunks but not into the floor
+        assert_eq!(get_light(&world, glam::ivec3(5, 40, 5)), Light::SKY);
+        assert_eq!(get_light(&world, glam::ivec3(5, 1, 5)), Light::SKY);
+        assert_eq!(get_light(&world, glam::ivec3(5, 0, 5)), Light::default());
+
+        // A roof casts a shadow that gets darker further under it
+        for pos in iter_3d(0, 7).map(|pos| pos + glam::ivec3(0, 10, 0)) {
+            if pos.y == 10 {
+                set_block(&mut world, &blocks, pos, stone);
+            }
+        }
+        assert_eq!(get_light(&world, glam::ivec3(3, 5, 3)).sky(), MAX_LIGHT - 4);
+        assert_eq!(get_light(&world, glam::ivec3(6, 5, 3)).sky(), MAX_LIGHT - 1);
+        assert_eq!(get_light(&world, glam::ivec3(3, 11, 3)), Light::SKY);
+
+        // Taking the roof away lets the sky back in
+        for pos in iter_3d(0, 7).map(|pos| pos + glam::ivec3(0, 10, 0)) {
+            if pos.y == 10 {
+                set_block(&mut world, &blocks, pos, AIR);
+            }
+        }
+        assert_eq!(get_light(&world, glam::ivec3(3, 5, 3)), Light::SKY);
+    }
+
+    #[test]
+    fn block_light() {
+        let blocks = BlockRegistry::from_ron(
+            r#"[
+                (id: 0, name: "air", solid: false, transparent: true),
+                (id: 1, name: "stone", solid: true, transparent: false),
+                (id: 2, name: "lamp", solid: true, transparent: false, light_emission: 10),
+            ]"#,
+        )
+        .unwrap();
+        let stone = blocks.id("stone").unwrap();
+        let lamp = blocks.id("lamp").unwrap();
+
+        // A closed box with no sky light
+        let mut world = HashMap::new();
+        for chunk_pos in iter_3d(-1, 1) {
+            let mut chunk = Chunk::new(chunk_pos);
+            for pos in iter_3d(0, CHUNK_SIZE as i32) {
+                chunk.set_block(pos.as_uvec3(), stone);
+            }
+            world.insert(chunk_pos, chunk);
+        }
+        for pos in iter_3d(-8, 8) {
+            set_block(&mut world, &blocks, pos, AIR);
+        }
+        assert_eq!(get_light(&world, glam::ivec3(0, 0, 0)), Light::default());
+
+        // Light goes across chunk borders and around corners
+        let changed = set_block(&mut world, &blocks, glam::ivec3(0, 0, 0), lamp);
+        assert_eq!(changed.len(), 8);
+        assert_eq!(get_light(&world, glam::ivec3(0, 0, 0)).block(), 10);
+        assert_eq!(get_light(&world, glam::ivec3(-1, 0, 0)).block(), 9);
+        assert_eq!(get_light(&world, glam::ivec3(-3, -2, 1)).block(), 4);
+        assert_eq!(get_light(&world, glam::ivec3(-9, 0, 0)).block(), 0);
+
+        // A second light keeps lighting what it reaches when the first is removed
+        set_block(&mut world, &blocks, glam::ivec3(-5, 0, 0), lamp);
+        set_block(&mut world, &blocks, glam::ivec3(0, 0, 0), AIR);
+        assert_eq!(get_light(&world, glam::ivec3(0, 0, 0)).block(), 5);
+        assert_eq!(get_light(&world, glam::ivec3(4, 0, 0)).block(), 1);
+        assert_eq!(get_light(&world, glam::ivec3(-4, 0, 0)).block(), 9);
+    }
+
+    #[test]
+    fn only_changes_returned() {
+        let blocks = BlockRegistry::default();
+        let stone = blocks.id("stone").unwrap();
+        let mut world = floor_world(&blocks);
+
+        // The block itself goes dark along with the column under it
+        let pos = glam::ivec3(5, 20, 5);
+        let changes = set_block(&mut world, &blocks, pos, stone);
+        assert_eq!(changes.len(), 1);
+        let changes = &changes[&glam::IVec3::ZERO];
+        assert_eq!(changes[&pos], Light::default());
+        assert_eq!(changes[&(pos - glam::IVec3::Y)].sky(), MAX_LIGHT - 1);
+        assert!(!changes.contains_key(&(pos + glam::IVec3::Y)));
+        for (pos, light) in changes {
+            assert_eq!(get_light(&world, *pos), *light);
+        }
+
+        // Light that gets taken away then spread back the same isn't a change, like replacing
+        // the floor with the same block
+        assert!(update_light(&mut world, &blocks, [pos]).is_empty());
+        assert!(set_block(&mut world, &blocks, pos - glam::ivec3(0, 20, 0), stone).is_empty());
+    }
+
+    #[test]
+    fn same_in_any_chunk_order() {
+        let blocks = BlockRegistry::default();
+        let stone = blocks.id("stone").unwrap();
+        let light_world = |order: &[glam::IVec3]| {
+            let mut world = HashMap::new();
+            for chunk_pos in order {
+                // Hills with gaps going all the way down
+                let mut chunk = Chunk::new(*chunk_pos);
+                for pos in iter_3d(0, CHUNK_SIZE as i32) {
+                    let block_pos = *chunk_pos * CHUNK_SIZE as i32 + pos;
+                    let (x, z) = (block_pos.x as f32, block_pos.z as f32);
+                    let height = (x / 10.0).sin() * 10.0 + (z / 7.0).cos() * 8.0;
+                    if block_pos.y < height as i32 && (block_pos.x + block_pos.z) % 13 != 0 {
+                        chunk.set_block(pos.as_uvec3(), stone);
+                    }
+                }
+                world.insert(*chunk_pos, chunk);
+                light_chunk(&mut world, &blocks, *chunk_pos);
+            }
+            world
+        };
+
+        // Chunks get added below and above already lit chunks
+        let order = iter_3d(-1, 1).collect::<Vec<_>>();
+        let reversed = order.iter().rev().copied().collect::<Vec<_>>();
+        let first = light_world(&order);
+        let second = light_world(&reversed);
+        for (chunk_pos, chunk) in &first {
+            for pos in iter_3d(0, CHUNK_SIZE as i32).map(|pos| pos.as_uvec3()) {
+                assert_eq!(chunk.get_light(pos), second[chunk_pos].get_light(pos));
+            }
+        }
+    }
+}
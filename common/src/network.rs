@@ -1,31 +1,177 @@
+use bincode::Options;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
-    io::Write,
+    io::{Read, Write},
     net::{SocketAddr, TcpStream},
 };
 
-use crate::Chunk;
+use crate::{Biome, BlockID, BlockRegistry, Chunk, Light};
 
-pub type ErrorKind = bincode::ErrorKind;
-pub type Result<T> = bincode::Result<T>;
+/// Bump whenever the layout of a request or response changes
+pub const PROTOCOL_VERSION: u32 = 10;
+/// Largest frame that can be sent or received unless changed with set_max_frame_size
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1 << 20;
+pub const MAX_PLAYER_NAME_LEN: usize = 32;
+
+/// Identifies an entity sent by the server, players keep theirs until they leave
+pub type EntityID = u32;
+
+/// Sent to the client when the server accepts its hello
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub world_seed: u64,
+    pub spawn_position: glam::Vec3,
+    pub tick_rate: u32,
+    /// Checksum of the block registry that gets sent after the welcome
+    pub block_registry_checksum: u64,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Encoding(bincode::Error),
+    /// The length in a frame header was bigger than the max frame size
+    FrameTooLarge {
+        size: usize,
+        max: usize,
+    },
+    /// The connection closed part way through a frame
+    TruncatedFrame {
+        expected: usize,
+        received: usize,
+    },
+    /// The other side sent something it shouldn't have
+    Protocol(String),
+    /// The server closed the connection with a reason
+    Disconnected(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Encoding(err) => write!(f, "invalid message - {}", err),
+            Self::FrameTooLarge { size, max } => {
+                write!(
+                    f,
+                    "frame of {} bytes is larger than the max of {}",
+                    size, max
+                )
+            }
+            Self::TruncatedFrame { expected, received } => write!(
+                f,
+                "connection closed after {} bytes of a {} byte frame",
+                received, expected
+            ),
+            Self::Protocol(message) => write!(f, "{}", message),
+            Self::Disconnected(reason) => write!(f, "disconnected - {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(err: bincode::Error) -> Self {
+        Self::Encoding(err)
+    }
+}
+
+impl Error {
+    /// Whether a read timed out before a full frame arrived, the read can be tried again
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Self::Io(err) if matches!(
+            err.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+        ))
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Request {
+    /// Has to be the first request sent, the layout must never change so servers on any
+    /// version can read it
+    Hello {
+        protocol_version: u32,
+        player_name: String,
+    },
     ChunkRange {
         start: glam::IVec3,
         end: glam::IVec3,
     },
+    /// The client has dropped the chunks so it doesn't need updates to them anymore
+    UnloadChunks(Vec<glam::IVec3>),
+    /// Where the player is and the yaw and pitch it's looking at in degrees, sent at a fixed rate
+    PlayerTransform {
+        position: glam::Vec3,
+        rotation: glam::Vec2,
+    },
+    /// Breaks or places the block at a world block position
+    SetBlock { pos: glam::IVec3, id: BlockID },
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Response {
-    ChunkData(Chunk),
-    Test,
+    /// Sent before the server closes the connection, must stay the first variant with the same
+    /// layout so clients on any version can read it
+    Disconnect(String),
+    Welcome(ServerInfo),
+    /// Sent after the welcome, defines what each block id means
+    BlockRegistry(BlockRegistry),
+    /// The biome of each block column in a column of chunks, x first then z, sent before the
+    /// first chunk in the column
+    ColumnBiomes {
+        column: glam::IVec2,
+        biomes: Vec<Biome>,
+    },
+    /// A requested chunk with its light
+    ChunkData(Box<Chunk>),
+    /// A block in a loaded chunk has changed
+    BlockUpdate {
+        pos: glam::IVec3,
+        id: BlockID,
+    },
+    /// The new light of blocks in a loaded chunk, sent after the blocks changing it
+    LightUpdate(Vec<(glam::IVec3, Light)>),
+    /// Another player has come into the loaded chunks
+    SpawnEntity {
+        id: EntityID,
+        name: String,
+        position: glam::Vec3,
+        rotation: glam::Vec2,
+    },
+    /// A spawned entity has moved
+    MoveEntity {
+        id: EntityID,
+        position: glam::Vec3,
+        rotation: glam::Vec2,
+    },
+    /// A spawned entity has left or gone out of the loaded chunks
+    DespawnEntity(EntityID),
+    /// The player moved somewhere it wasn't allowed to and has to go back to the position
+    SetPosition {
+        position: glam::Vec3,
+    },
 }
 
+/// Sends and receives messages as frames of a little endian u32 length followed by the
+/// bincode encoded message
 pub struct Protocol {
     reader: std::io::BufReader<TcpStream>,
     pub stream: TcpStream,
+    max_frame_size: usize,
+    /// The part of a frame received so far, kept if a read times out part way through a frame
+    frame: Vec<u8>,
+    /// Length of the frame being received once its header has been read
+    frame_len: Option<usize>,
 }
 
 impl Protocol {
@@ -33,14 +179,21 @@ impl Protocol {
         Ok(Self {
             reader: std::io::BufReader::new(stream.try_clone()?),
             stream,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            frame: Vec::new(),
+            frame_len: None,
         })
     }
 
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+    }
+
     /// Tries to connect to address n number of tries waiting 1 second in between
     pub fn connect(address: SocketAddr, tries: u8) -> Result<Self> {
         match TcpStream::connect(address) {
             Err(err) => {
-                if tries <= 0 {
+                if tries == 0 {
                     Err(err)?
                 } else {
                     std::thread::sleep(std::time::Duration::from_secs(1));
@@ -52,12 +205,243 @@ impl Protocol {
     }
 
     pub fn send(&mut self, data: &impl Serialize) -> Result<()> {
-        bincode::serialize_into(&mut self.stream, data)?;
+        let size = self.options().serialized_size(data)? as usize;
+        if size > self.max_frame_size {
+            return Err(Error::FrameTooLarge {
+                size,
+                max: self.max_frame_size,
+            });
+        }
+
+        let mut frame = Vec::with_capacity(4 + size);
+        frame.extend_from_slice(&(size as u32).to_le_bytes());
+        self.options().serialize_into(&mut frame, data)?;
+        self.stream.write_all(&frame)?;
         self.stream.flush()?;
         Ok(())
     }
 
+    /// Reads the next message, if the stream has a read timeout and it times out the read can be
+    /// tried again without losing data
     pub fn read<T: DeserializeOwned>(&mut self) -> Result<T> {
-        bincode::deserialize_from(&mut self.reader)
+        let frame = self.read_frame()?;
+        Ok(self.options().deserialize(&frame)?)
+    }
+
+    fn read_frame(&mut self) -> Result<Vec<u8>> {
+        loop {
+            let expected = self.frame_len.unwrap_or(4);
+            let remaining = (expected - self.frame.len()) as u64;
+            // Bytes read before an error are still added to the frame
+            (&mut self.reader)
+                .take(remaining)
+                .read_to_end(&mut self.frame)?;
+
+            if self.frame.len() < expected {
+                let received = self.frame.len();
+                self.frame.clear();
+                // Closing the connection between frames is not an invalid frame
+                return Err(if self.frame_len.take().is_none() && received == 0 {
+                    Error::Io(std::io::ErrorKind::UnexpectedEof.into())
+                } else {
+                    Error::TruncatedFrame { expected, received }
+                });
+            }
+
+            match self.frame_len.take() {
+                Some(_) => return Ok(std::mem::take(&mut self.frame)),
+                None => {
+                    let size = u32::from_le_bytes(self.frame[..].try_into().unwrap()) as usize;
+                    self.frame.clear();
+                    if size > self.max_frame_size {
+                        return Err(Error::FrameTooLarge {
+                            size,
+                            max: self.max_frame_size,
+                        });
+                    }
+                    self.frame_len = Some(size);
+                }
+            }
+        }
+    }
+
+    fn options(&self) -> impl Options {
+        bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .with_limit(self.max_frame_size as u64)
+    }
+
+    /// Says hello to the server then waits for it to accept the connection and send the block
+    /// registry
+    pub fn handshake(&mut self, player_name: &str) -> Result<(ServerInfo, BlockRegistry)> {
+        self.send(&Request::Hello {
+            protocol_version: PROTOCOL_VERSION,
+            player_name: player_name.to_owned(),
+        })?;
+
+        let info = match self.read::<Response>()? {
+            Response::Welcome(info) => info,
+            Response::Disconnect(reason) => return Err(Error::Disconnected(reason)),
+            response => {
+                return Err(Error::Protocol(format!(
+                    "Expected welcome but got {:?}",
+                    response
+                )))
+            }
+        };
+
+        match self.read::<Response>()? {
+            Response::BlockRegistry(registry) => {
+                if registry.checksum() != info.block_registry_checksum {
+                    return Err(Error::Protocol(
+                        "Block registry doesn't match the checksum".to_owned(),
+                    ));
+                }
+                Ok((info, registry))
+            }
+            Response::Disconnect(reason) => Err(Error::Disconnected(reason)),
+            response => Err(Error::Protocol(format!(
+                "Expected block registry but got {:?}",
+                response
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    /// Gets a connected stream and the protocol reading from the other end of it
+    fn loopback() -> (TcpStream, Protocol) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server_stream, _) = listener.accept().unwrap();
+        (stream, Protocol::with_stream(server_stream).unwrap())
+    }
+
+    /// Xorshift so the random bytes are the same every run
+    fn random_bytes(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed | 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let (stream, mut protocol) = loopback();
+        let mut sender = Protocol::with_stream(stream).unwrap();
+        let request = Request::ChunkRange {
+            start: glam::ivec3(-1, 2, -3),
+            end: glam::ivec3(4, 5, 6),
+        };
+        sender.send(&request).unwrap();
+        sender.send(&request).unwrap();
+
+        for _ in 0..2 {
+            assert!(matches!(
+                protocol.read::<Request>().unwrap(),
+                Request::ChunkRange { start, end } if start == glam::ivec3(-1, 2, -3) && end == glam::ivec3(4, 5, 6)
+            ));
+        }
+    }
+
+    #[test]
+    fn invalid_frames() {
+        let (mut stream, mut protocol) = loopback();
+        protocol.set_max_frame_size(1024);
+        stream.write_all(&2048u32.to_le_bytes()).unwrap();
+        assert!(matches!(
+            protocol.read::<Request>(),
+            Err(Error::FrameTooLarge {
+                size: 2048,
+                max: 1024
+            })
+        ));
+
+        let (mut stream, mut protocol) = loopback();
+        stream.write_all(&100u32.to_le_bytes()).unwrap();
+        stream.write_all(&[0; 10]).unwrap();
+        drop(stream);
+        assert!(matches!(
+            protocol.read::<Request>(),
+            Err(Error::TruncatedFrame {
+                expected: 100,
+                received: 10
+            })
+        ));
+
+        // The length says there's more data than the message uses
+        let (mut stream, mut protocol) = loopback();
+        stream.write_all(&8u32.to_le_bytes()).unwrap();
+        stream.write_all(&[0; 8]).unwrap();
+        assert!(matches!(
+            protocol.read::<Response>(),
+            Err(Error::Encoding(_))
+        ));
+    }
+
+    #[test]
+    fn resumes_after_timeout() {
+        let (stream, mut protocol) = loopback();
+        protocol
+            .stream
+            .set_read_timeout(Some(std::time::Duration::from_millis(10)))
+            .unwrap();
+
+        let mut frame = Vec::new();
+        let data = bincode::serialize(&Response::Disconnect("reason".to_owned())).unwrap();
+        frame.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&data);
+
+        let mut stream = stream;
+        stream.write_all(&frame[..6]).unwrap();
+        assert!(protocol.read::<Response>().unwrap_err().is_timeout());
+        stream.write_all(&frame[6..]).unwrap();
+        assert!(matches!(
+            protocol.read::<Response>().unwrap(),
+            Response::Disconnect(reason) if reason == "reason"
+        ));
+    }
+
+    #[test]
+    fn fuzz_random_bytes() {
+        for seed in 0..200 {
+            let (mut stream, mut protocol) = loopback();
+            protocol.set_max_frame_size(4096);
+
+            let mut bytes = random_bytes(seed, 1 + seed as usize * 37);
+            // Make some of the frames small enough to get past the length check
+            if seed % 2 == 0 {
+                let len = (seed as u32 * 7) % 64;
+                bytes.splice(0..0, len.to_le_bytes());
+            }
+            stream.write_all(&bytes).unwrap();
+            drop(stream);
+
+            // Has to end with an error without panicking or hanging
+            loop {
+                match protocol.read::<Request>() {
+                    Ok(_) => continue,
+                    Err(Error::Io(err)) => {
+                        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+                        break;
+                    }
+                    Err(
+                        Error::Encoding(_)
+                        | Error::FrameTooLarge { .. }
+                        | Error::TruncatedFrame { .. },
+                    ) => break,
+                    Err(err) => panic!("Unexpected error {}", err),
+                }
+            }
+        }
     }
 }
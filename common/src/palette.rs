@@ -0,0 +1,215 @@
+use crate::{BlockID, CHUNK_VOLUME};
+
+/// Block storage for a chunk where every block is an index into a palette of the block ids
+/// used in the chunk
+///
+/// The indices are bit packed with just enough bits to index the palette, so a chunk with only
+/// a few block types stays small no matter how big BlockID is. A chunk with only one block type
+/// (eg. all air) stores no indices at all.
+#[derive(Clone)]
+pub struct PalettedStorage {
+    palette: Vec<BlockID>,
+    /// Amount of blocks using each palette entry so unused entries can be reused
+    counts: Vec<u32>,
+    /// Bits per index, always a power of two so indices never cross a word boundary
+    bits: u32,
+    data: Vec<u64>,
+}
+
+impl PalettedStorage {
+    pub fn filled(id: BlockID) -> Self {
+        Self {
+            palette: vec![id],
+            counts: vec![CHUNK_VOLUME as u32],
+            bits: 0,
+            data: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, index: usize) -> BlockID {
+        self.palette[self.get_index(index)]
+    }
+
+    pub fn set(&mut self, index: usize, id: BlockID) {
+        self.set_range(index..index + 1, id);
+    }
+
+    /// Sets every block in the range to the id
+    pub fn set_range(&mut self, range: std::ops::Range<usize>, id: BlockID) {
+        let palette_index = self.palette_index(id);
+        // Nothing to set when the chunk only contains this id
+        if self.bits == 0 {
+            return;
+        }
+
+        for index in range {
+            let old_index = self.get_index(index);
+            if old_index != palette_index {
+                self.counts[old_index] -= 1;
+                self.counts[palette_index] += 1;
+                self.set_index(index, palette_index);
+            }
+        }
+
+        // Go back to storing a single value once the id fills the entire chunk
+        if self.counts[palette_index] == CHUNK_VOLUME as u32 {
+            *self = Self::filled(id);
+        }
+    }
+
+    /// Returns the id if every block in the storage is the same
+    pub fn single_value(&self) -> Option<BlockID> {
+        (self.bits == 0).then(|| self.palette[0])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = BlockID> + '_ {
+        (0..CHUNK_VOLUME).map(|index| self.get(index))
+    }
+
+    /// Gets the index of the id in the palette, adding it if it's not there
+    fn palette_index(&mut self, id: BlockID) -> usize {
+        if let Some(index) = self.palette.iter().position(|palette_id| *palette_id == id) {
+            return index;
+        }
+
+        if let Some(index) = self.counts.iter().position(|count| *count == 0) {
+            self.palette[index] = id;
+            return index;
+        }
+
+        self.palette.push(id);
+        self.counts.push(0);
+        let len = self.palette.len();
+        if len > 1 << self.bits {
+            let bits = (usize::BITS - (len - 1).leading_zeros()).next_power_of_two();
+            self.resize(bits);
+        }
+        len - 1
+    }
+
+    /// Repacks the indices using a different amount of bits
+    fn resize(&mut self, bits: u32) {
+        let mut new = Self {
+            palette: std::mem::take(&mut self.palette),
+            counts: std::mem::take(&mut self.counts),
+            bits,
+            data: vec![0; CHUNK_VOLUME / (u64::BITS / bits) as usize],
+        };
+
+        if self.bits != 0 {
+            for index in 0..CHUNK_VOLUME {
+                new.set_index(index, self.get_index(index));
+            }
+        }
+
+        *self = new;
+    }
+
+    fn get_index(&self, index: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+
+        let (word, shift) = self.locate(index);
+        let mask = (1 << self.bits) - 1;
+        ((self.data[word] >> shift) & mask) as usize
+    }
+
+    fn set_index(&mut self, index: usize, palette_index: usize) {
+        let (word, shift) = self.locate(index);
+        let mask = ((1 << self.bits) - 1) << shift;
+        self.data[word] = (self.data[word] & !mask) | ((palette_index as u64) << shift);
+    }
+
+    /// Gets the word containing the index and the bit offset into that word
+    fn locate(&self, index: usize) -> (usize, u32) {
+        let per_word = (u64::BITS / self.bits) as usize;
+        (index / per_word, (index % per_word) as u32 * self.bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bits_grow() {
+        let mut storage = PalettedStorage::filled(0);
+
+        // Each id goes into its own block, the bits double every time the palette outgrows them
+        let mut bits = Vec::new();
+        for id in 1..300 {
+            storage.set(id as usize * 7, id);
+            bits.push(storage.bits);
+        }
+        let bits_at = |id: usize| bits[id - 1];
+        assert_eq!(bits_at(1), 1);
+        assert_eq!(bits_at(3), 2);
+        assert_eq!(bits_at(4), 4);
+        assert_eq!(bits_at(16), 8);
+        assert_eq!(bits_at(256), 16);
+
+        // Nothing gets lost when repacking
+        for id in 1..300 {
+            assert_eq!(storage.get(id as usize * 7), id);
+            assert_eq!(storage.get(id as usize * 7 + 1), 0);
+        }
+        assert_eq!(storage.single_value(), None);
+    }
+
+    #[test]
+    fn range_crosses_words() {
+        let mut storage = PalettedStorage::filled(0);
+        for id in 1..5 {
+            storage.set(CHUNK_VOLUME - id as usize, id);
+        }
+        assert_eq!(storage.bits, 4);
+
+        // 16 indices to a word, so this covers the end of one, a whole one and the start of another
+        storage.set_range(10..40, 9);
+        for index in 0..50 {
+            let expected = if (10..40).contains(&index) { 9 } else { 0 };
+            assert_eq!(storage.get(index), expected, "index {}", index);
+        }
+        assert_eq!(storage.get(CHUNK_VOLUME - 1), 1);
+    }
+
+    #[test]
+    fn freed_entries_reused() {
+        let mut storage = PalettedStorage::filled(0);
+        storage.set(0, 1);
+        storage.set(1, 2);
+        assert_eq!(storage.palette, [0, 1, 2]);
+
+        // The last block using 1 is gone so 3 takes its place instead of growing the palette
+        storage.set(0, 0);
+        storage.set(2, 3);
+        assert_eq!(storage.palette, [0, 3, 2]);
+        assert_eq!(storage.counts, [CHUNK_VOLUME as u32 - 2, 1, 1]);
+        assert_eq!(storage.bits, 2);
+        assert_eq!(storage.get(2), 3);
+        assert_eq!(storage.get(1), 2);
+    }
+
+    #[test]
+    fn shrinks_when_filled() {
+        let mut storage = PalettedStorage::filled(0);
+        for id in 1..20 {
+            storage.set(id as usize, id);
+        }
+        assert_eq!(storage.bits, 8);
+
+        // Filling over everything goes back to a single value with no indices
+        storage.set_range(0..CHUNK_VOLUME, 5);
+        assert_eq!(storage.single_value(), Some(5));
+        assert_eq!(storage.bits, 0);
+        assert!(storage.data.is_empty());
+        assert_eq!(storage.palette, [5]);
+
+        // And grows again from there
+        storage.set(3, 6);
+        assert_eq!(storage.bits, 1);
+        assert_eq!(storage.get(3), 6);
+        assert_eq!(storage.get(4), 5);
+    }
+}
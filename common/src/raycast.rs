@@ -0,0 +1,133 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastHit {
+    pub block_pos: glam::IVec3,
+    /// Points out of the face of the block that was hit, zero if the ray started inside of it
+    pub normal: glam::IVec3,
+    pub distance: f32,
+}
+
+/// Walks through every block the ray passes through in order until is_solid returns true for
+/// one of them, is_solid gets given world block positions so the ray can cross chunks
+pub fn raycast(
+    origin: glam::Vec3,
+    direction: glam::Vec3,
+    max_distance: f32,
+    mut is_solid: impl FnMut(glam::IVec3) -> bool,
+) -> Option<RaycastHit> {
+    let direction = direction.normalize_or_zero();
+    if direction == glam::Vec3::ZERO {
+        return None;
+    }
+
+    let mut block_pos = origin.floor().as_ivec3();
+    if is_solid(block_pos) {
+        return Some(RaycastHit {
+            block_pos,
+            normal: glam::IVec3::ZERO,
+            distance: 0.0,
+        });
+    }
+
+    let mut step = glam::IVec3::ZERO;
+    // Distance along the ray to the next block boundary on each axis
+    let mut next_boundary = glam::Vec3::splat(f32::INFINITY);
+    // Distance along the ray between block boundaries on each axis
+    let mut boundary_delta = glam::Vec3::splat(f32::INFINITY);
+    for axis in 0..3 {
+        if direction[axis] > 0.0 {
+            step[axis] = 1;
+            next_boundary[axis] = (block_pos[axis] as f32 + 1.0 - origin[axis]) / direction[axis];
+        } else if direction[axis] < 0.0 {
+            step[axis] = -1;
+            next_boundary[axis] = (block_pos[axis] as f32 - origin[axis]) / direction[axis];
+        } else {
+            continue;
+        }
+        boundary_delta[axis] = 1.0 / direction[axis].abs();
+    }
+
+    loop {
+        let axis = if next_boundary.x < next_boundary.y && next_boundary.x < next_boundary.z {
+            0
+        } else if next_boundary.y < next_boundary.z {
+            1
+        } else {
+            2
+        };
+
+        let distance = next_boundary[axis];
+        if distance > max_distance {
+            return None;
+        }
+
+        block_pos[axis] += step[axis];
+        next_boundary[axis] += boundary_delta[axis];
+
+        if is_solid(block_pos) {
+            let mut normal = glam::IVec3::ZERO;
+            normal[axis] = -step[axis];
+            return Some(RaycastHit {
+                block_pos,
+                normal,
+                distance,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hits_first_solid_block() {
+        let wall = |pos: glam::IVec3| pos.x >= 3;
+        let hit = raycast(glam::vec3(0.5, 0.5, 0.5), glam::Vec3::X, 10.0, wall).unwrap();
+        assert_eq!(hit.block_pos, glam::ivec3(3, 0, 0));
+        assert_eq!(hit.normal, glam::ivec3(-1, 0, 0));
+        assert!((hit.distance - 2.5).abs() < 1e-5);
+
+        assert_eq!(
+            raycast(glam::vec3(0.5, 0.5, 0.5), glam::Vec3::X, 2.0, wall),
+            None
+        );
+        assert_eq!(
+            raycast(glam::vec3(0.5, 0.5, 0.5), glam::Vec3::ZERO, 10.0, wall),
+            None
+        );
+    }
+
+    #[test]
+    fn crosses_negative_coordinates() {
+        // Floor below y = -40 across chunk borders
+        let floor = |pos: glam::IVec3| pos.y < -40;
+        let origin = glam::vec3(-0.5, 0.5, -0.5);
+        let direction = glam::vec3(-1.0, -2.0, -1.0);
+        let hit = raycast(origin, direction, 100.0, floor).unwrap();
+        assert_eq!(hit.block_pos.y, -41);
+        assert_eq!(hit.normal, glam::ivec3(0, 1, 0));
+
+        let hit_point = origin + direction.normalize() * hit.distance;
+        assert!((hit_point.y + 40.0).abs() < 1e-3);
+        assert_eq!(hit_point.floor().as_ivec3().x, hit.block_pos.x);
+
+        // Every block along the way gets checked in order
+        let mut visited = Vec::new();
+        raycast(origin, glam::vec3(-1.0, 0.0, -1.0), 3.0, |pos| {
+            visited.push(pos);
+            false
+        });
+        for pair in visited.windows(2) {
+            let offset = (pair[1] - pair[0]).abs();
+            assert_eq!(offset.x + offset.y + offset.z, 1);
+        }
+    }
+
+    #[test]
+    fn starts_inside_block() {
+        let hit = raycast(glam::vec3(1.5, 1.5, 1.5), glam::Vec3::Y, 5.0, |_| true).unwrap();
+        assert_eq!(hit.block_pos, glam::ivec3(1, 1, 1));
+        assert_eq!(hit.normal, glam::IVec3::ZERO);
+        assert_eq!(hit.distance, 0.0);
+    }
+}
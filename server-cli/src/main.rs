@@ -1,5 +1,6 @@
 use clap::Parser;
-use opencuboids_common::DEFAULT_PORT;
+use opencuboids_common::{BlockRegistry, DEFAULT_PORT};
+use opencuboids_server::MovementLimits;
 
 /// Cli for the opencuboids server
 #[derive(Parser, Debug)]
@@ -8,6 +9,26 @@ struct Args {
     /// The port of the server to run on
     #[clap(short, long, value_parser, default_value_t = DEFAULT_PORT)]
     port: u16,
+
+    /// The directory the world gets saved to
+    #[clap(short, long, value_parser, default_value = "world")]
+    world: std::path::PathBuf,
+
+    /// RON file with the block definitions, uses the built in blocks if not set
+    #[clap(short, long, value_parser)]
+    blocks: Option<std::path::PathBuf>,
+
+    /// How many times the world gets updated per second
+    #[clap(short, long, value_parser = clap::value_parser!(u32).range(1..), default_value_t = opencuboids_server::DEFAULT_TICK_RATE)]
+    tick_rate: u32,
+
+    /// Blocks per second players can move before they get sent back
+    #[clap(long, value_parser, default_value_t = MovementLimits::default().max_speed)]
+    max_speed: f32,
+
+    /// Extra blocks players can move on top of the max speed to make up for lag
+    #[clap(long, value_parser, default_value_t = MovementLimits::default().tolerance)]
+    move_tolerance: f32,
 }
 
 fn main() {
@@ -15,5 +36,42 @@ fn main() {
 
     let args = Args::parse();
     let address = format!("0.0.0.0:{}", args.port).parse().unwrap();
-    opencuboids_server::start(address);
+    let block_registry = match args.blocks {
+        Some(path) => match BlockRegistry::load(&path) {
+            Ok(registry) => registry,
+            Err(err) => {
+                log::error!("Failed to load blocks from {} - {}", path.display(), err);
+                return;
+            }
+        },
+        None => BlockRegistry::default(),
+    };
+
+    let mut server = match opencuboids_server::Server::bind(address, args.world, block_registry) {
+        Ok(server) => server,
+        Err(err) => {
+            log::error!("Failed to start server on {} - {}", address, err);
+            return;
+        }
+    };
+
+    server.set_tick_rate(args.tick_rate);
+    server.set_movement_limits(MovementLimits {
+        max_speed: args.max_speed,
+        tolerance: args.move_tolerance,
+    });
+
+    // Save the world before exiting on ctrl-c
+    let world_save = server.world_save();
+    ctrlc::set_handler(move || {
+        if let Err(err) = world_save.lock().unwrap().flush() {
+            log::error!("Failed to save world - {}", err);
+        }
+        std::process::exit(0);
+    })
+    .expect("Failed to set ctrl-c handler");
+
+    if let Err(err) = server.run() {
+        log::error!("Server stopped - {}", err);
+    }
 }
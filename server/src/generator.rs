@@ -0,0 +1,183 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    sync::{mpsc, Arc, Condvar, Mutex},
+};
+
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use opencuboids_common::{BlockID, Chunk};
+
+use crate::world::World;
+
+/// A generated chunk along with the blocks of its structures that go into other chunks
+pub type Generated = (Chunk, Vec<(glam::IVec3, BlockID)>);
+
+/// Chunks waiting to be generated, lower priorities go first
+#[derive(Default)]
+struct Queue {
+    /// Can have old entries for chunks that got moved up or cancelled, they get skipped if the
+    /// priority doesn't match the one in queued
+    jobs: BinaryHeap<Reverse<(u64, [i32; 3])>>,
+    queued: HashMap<glam::IVec3, u64>,
+    stopped: bool,
+}
+
+impl Queue {
+    fn push(&mut self, chunk_pos: glam::IVec3, priority: u64) {
+        self.queued.insert(chunk_pos, priority);
+        self.jobs.push(Reverse((priority, chunk_pos.to_array())));
+    }
+
+    fn pop(&mut self) -> Option<glam::IVec3> {
+        while let Some(Reverse((priority, chunk_pos))) = self.jobs.pop() {
+            let chunk_pos = glam::IVec3::from(chunk_pos);
+            if self.queued.get(&chunk_pos) == Some(&priority) {
+                self.queued.remove(&chunk_pos);
+                return Some(chunk_pos);
+            }
+        }
+        None
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    job_added: Condvar,
+    /// How many jobs the workers have taken
+    #[cfg(test)]
+    taken: AtomicUsize,
+}
+
+/// Generates chunks on a pool of worker threads, the closest chunks to the players go first
+///
+/// Only the noise part of generating gets done on the workers, the results still need to be
+/// added to the world.
+pub struct ChunkGenerator {
+    shared: Arc<Shared>,
+    results: mpsc::Receiver<Generated>,
+}
+
+impl ChunkGenerator {
+    pub fn new(world: Arc<World>) -> Self {
+        // Leave a thread for the simulation
+        let workers = std::thread::available_parallelism().map_or(1, |count| count.get() - 1);
+        let shared = Arc::new(Shared::default());
+        let (sender, results) = mpsc::channel();
+
+        for _ in 0..workers.max(1) {
+            let shared = shared.clone();
+            let world = world.clone();
+            let sender = sender.clone();
+            std::thread::spawn(move || {
+                while let Some(chunk_pos) = shared.wait_for_job() {
+                    if sender.send(world.world_gen().gen_chunk(chunk_pos)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+
+        Self { shared, results }
+    }
+
+    /// Queues the chunk to be generated, a chunk that has already been queued or taken by a
+    /// worker shouldn't be requested again
+    pub fn request(&self, chunk_pos: glam::IVec3, priority: u64) {
+        self.shared.queue.lock().unwrap().push(chunk_pos, priority);
+        self.shared.job_added.notify_one();
+    }
+
+    /// Moves the chunk up the queue if it's still waiting there and the priority is lower
+    pub fn prioritise(&self, chunk_pos: glam::IVec3, priority: u64) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        if queue
+            .queued
+            .get(&chunk_pos)
+            .is_some_and(|old| priority < *old)
+        {
+            queue.push(chunk_pos, priority);
+        }
+    }
+
+    /// Takes the chunk out of the queue, chunks already taken by a worker still get generated
+    pub fn cancel(&self, chunk_pos: glam::IVec3) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        queue.queued.remove(&chunk_pos);
+
+        // Clear out the jobs that will be skipped if there's too many
+        if queue.jobs.len() > queue.queued.len() * 2 + 64 {
+            let Queue { jobs, queued, .. } = &mut *queue;
+            jobs.retain(|Reverse((priority, chunk_pos))| {
+                queued.get(&glam::IVec3::from(*chunk_pos)) == Some(priority)
+            });
+        }
+    }
+
+    /// Gets a chunk that has finished generating if there is one
+    pub fn try_recv(&self) -> Option<Generated> {
+        self.results.try_recv().ok()
+    }
+
+    #[cfg(test)]
+    pub fn taken(&self) -> usize {
+        self.shared.taken.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for ChunkGenerator {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().stopped = true;
+        self.shared.job_added.notify_all();
+    }
+}
+
+impl Shared {
+    /// Blocks until there's a chunk to generate, returns none once the generator is dropped
+    fn wait_for_job(&self) -> Option<glam::IVec3> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if queue.stopped {
+                return None;
+            }
+
+            if let Some(chunk_pos) = queue.pop() {
+                #[cfg(test)]
+                self.taken.fetch_add(1, Ordering::SeqCst);
+                return Some(chunk_pos);
+            }
+            queue = self.job_added.wait(queue).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_order() {
+        let mut queue = Queue::default();
+        queue.push(glam::ivec3(0, 0, 4), 16);
+        queue.push(glam::ivec3(0, 0, 1), 1);
+        queue.push(glam::ivec3(0, 3, 0), 9);
+        queue.push(glam::ivec3(2, 0, 0), 4);
+
+        // One gets moved up by a closer player and another gets cancelled
+        queue.push(glam::ivec3(0, 0, 4), 0);
+        queue.queued.remove(&glam::ivec3(0, 3, 0));
+
+        let order = std::iter::from_fn(|| queue.pop()).collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            [
+                glam::ivec3(0, 0, 4),
+                glam::ivec3(0, 0, 1),
+                glam::ivec3(2, 0, 0)
+            ]
+        );
+        assert!(queue.jobs.is_empty());
+    }
+}
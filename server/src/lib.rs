@@ -1,52 +1,566 @@
+mod generator;
+mod movement;
+mod region;
+mod simulation;
+mod world;
 mod world_gen;
+mod world_save;
 
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::{
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{mpsc, Arc, Mutex},
+};
 
-use opencuboids_common::{iter_3d_vec, network, Chunk};
+use opencuboids_common::{
+    network::{self, ServerInfo},
+    BlockRegistry,
+};
+use simulation::{ClientEvent, Simulation};
+use world::World;
+use world_gen::WorldGen;
 
-pub fn start(address: SocketAddr) {
-    if let Err(err) = bind(address) {
-        log::error!("Failed to start server on {} - {}", address, err);
-    }
+pub use movement::MovementLimits;
+pub use world_save::WorldSave;
+
+/// How often the dirty chunks get written to disk
+const SAVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+/// Ticks per second unless changed with set_tick_rate
+pub const DEFAULT_TICK_RATE: u32 = 20;
+
+/// State shared with every client thread
+#[derive(Clone)]
+struct Context {
+    block_registry: Arc<BlockRegistry>,
+    info: ServerInfo,
+    events: mpsc::Sender<ClientEvent>,
 }
 
-fn bind(address: SocketAddr) -> std::io::Result<()> {
-    let listener = TcpListener::bind(address)?;
-    log::info!("Server running on {}", listener.local_addr()?);
+pub struct Server {
+    listener: TcpListener,
+    context: Context,
+    world: Arc<World>,
+    simulation: Simulation,
+}
+
+impl Server {
+    pub fn bind(
+        address: SocketAddr,
+        world_dir: impl Into<PathBuf>,
+        block_registry: BlockRegistry,
+    ) -> std::io::Result<Self> {
+        let world_save = WorldSave::open(world_dir)?;
+        let world_gen = WorldGen::new(world_save.seed(), &block_registry)?;
+
+        // Players spawn standing on top of the surface at the middle of the world
+        let spawn_height = world_gen.surface_height(0, 0) as f32 + 3.0;
+        let info = ServerInfo {
+            world_seed: world_save.seed(),
+            spawn_position: glam::vec3(0.5, spawn_height, 0.5),
+            tick_rate: DEFAULT_TICK_RATE,
+            block_registry_checksum: block_registry.checksum(),
+        };
+
+        let block_registry = Arc::new(block_registry);
+        let world_save = Arc::new(Mutex::new(world_save));
+        let world = Arc::new(World::new(world_save, world_gen, block_registry.clone()));
+        let (events, receiver) = mpsc::channel();
+        let simulation = Simulation::new(world.clone(), info.spawn_position, receiver);
 
-    for stream in listener.incoming() {
-        let stream = stream?;
-        let addr = stream.peer_addr()?;
-        log::info!("Client connected at {}", addr);
-        std::thread::spawn(move || {
-            if handle_client(stream).is_err() {
-                log::info!("Client disconnected unexpectedly at {}", addr);
-            } else {
-                log::info!("Client disconnected at {}", addr);
+        Ok(Self {
+            listener: TcpListener::bind(address)?,
+            context: Context {
+                block_registry,
+                info,
+                events,
+            },
+            world,
+            simulation,
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Gets the world save so it can be flushed on shutdown
+    pub fn world_save(&self) -> Arc<Mutex<WorldSave>> {
+        self.world.world_save()
+    }
+
+    pub fn set_tick_rate(&mut self, tick_rate: u32) {
+        assert!(tick_rate > 0, "Tick rate has to be above 0");
+        self.context.info.tick_rate = tick_rate;
+        self.simulation.set_tick_rate(tick_rate);
+    }
+
+    /// Sets how far players can move before they get sent back
+    pub fn set_movement_limits(&mut self, limits: MovementLimits) {
+        self.simulation.set_movement_limits(limits);
+    }
+
+    pub fn run(self) -> std::io::Result<()> {
+        log::info!("Server running on {}", self.listener.local_addr()?);
+
+        let world_save = self.world_save();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(SAVE_INTERVAL);
+            if let Err(err) = world_save.lock().unwrap().flush() {
+                log::error!("Failed to save world - {}", err);
             }
         });
-    }
 
-    Ok(())
+        let simulation = self.simulation;
+        std::thread::spawn(move || simulation.run());
+
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let addr = stream.peer_addr()?;
+            log::info!("Client connected at {}", addr);
+            let context = self.context.clone();
+            std::thread::spawn(move || {
+                match handle_client(stream, &context) {
+                    Err(err) => log::info!("Client disconnected at {} - {}", addr, err),
+                    Ok(()) => log::info!("Client disconnected at {}", addr),
+                }
+                let _ = context.events.send(ClientEvent::Left { addr });
+            });
+        }
+
+        Ok(())
+    }
 }
 
-fn handle_client(stream: TcpStream) -> network::Result<()> {
+/// Waits for the client to say hello, disconnecting it with a reason if it can't join
+fn accept_player(protocol: &mut network::Protocol, context: &Context) -> network::Result<String> {
     use network::{Request, Response};
 
+    let reason = match read_request(protocol)? {
+        Request::Hello {
+            protocol_version, ..
+        } if protocol_version != network::PROTOCOL_VERSION => format!(
+            "Server is on protocol version {} but the client is on version {}",
+            network::PROTOCOL_VERSION,
+            protocol_version
+        ),
+        Request::Hello { player_name, .. }
+            if player_name.is_empty()
+                || player_name.chars().count() > network::MAX_PLAYER_NAME_LEN =>
+        {
+            format!(
+                "Player name has to be between 1 and {} characters long",
+                network::MAX_PLAYER_NAME_LEN
+            )
+        }
+        Request::Hello { player_name, .. } => {
+            protocol.send(&Response::Welcome(context.info.clone()))?;
+            protocol.send(&Response::BlockRegistry((*context.block_registry).clone()))?;
+            return Ok(player_name);
+        }
+        _ => "Expected hello as the first request".to_owned(),
+    };
+
+    Err(disconnect(protocol, reason))
+}
+
+/// Gets the reason to disconnect the client if the error came from it sending an invalid frame
+fn invalid_frame(err: &network::Error) -> Option<String> {
+    match err {
+        network::Error::Encoding(_) | network::Error::FrameTooLarge { .. } => {
+            Some(format!("Invalid request - {}", err))
+        }
+        _ => None,
+    }
+}
+
+/// Reads the next request, disconnecting the client if it sent an invalid frame
+fn read_request(protocol: &mut network::Protocol) -> network::Result<network::Request> {
+    protocol.read().map_err(|err| match invalid_frame(&err) {
+        Some(reason) => disconnect(protocol, reason),
+        None => err,
+    })
+}
+
+/// Tells the client why it's being disconnected
+fn disconnect(protocol: &mut network::Protocol, reason: String) -> network::Error {
+    // The client might already be gone so failing to send the reason doesn't matter
+    let _ = protocol.send(&network::Response::Disconnect(reason.clone()));
+    network::Error::Protocol(reason)
+}
+
+/// Tells the client why it's being disconnected after the responses queued before it
+fn queue_disconnect(sender: &mpsc::Sender<network::Response>, reason: String) -> network::Error {
+    let _ = sender.send(network::Response::Disconnect(reason.clone()));
+    network::Error::Protocol(reason)
+}
+
+fn handle_client(stream: TcpStream, context: &Context) -> network::Result<()> {
+    use network::Request;
+
+    let addr = stream.peer_addr()?;
     let mut protocol = network::Protocol::with_stream(stream)?;
+    let player_name = accept_player(&mut protocol, context)?;
+    log::info!("{} joined the game", player_name);
+
+    // Responses get written on their own thread so they don't have to wait for requests
+    let (sender, receiver) = mpsc::channel();
+    let mut writer = network::Protocol::with_stream(protocol.stream.try_clone()?)?;
+    std::thread::spawn(move || {
+        for response in receiver {
+            let disconnect = matches!(response, network::Response::Disconnect(_));
+            if writer.send(&response).is_err() || disconnect {
+                break;
+            }
+        }
+        // Every sender is gone once the client has left, or the simulation disconnected it
+        let _ = writer.stream.shutdown(Shutdown::Both);
+    });
+
+    let joined = ClientEvent::Joined {
+        addr,
+        player_name,
+        sender: sender.clone(),
+    };
+    if context.events.send(joined).is_err() {
+        return Err(queue_disconnect(&sender, "Server stopped".to_owned()));
+    }
+
     loop {
-        let request = protocol.read::<network::Request>()?;
-        log::info!("Received message: {:#?}", request);
-
-        match request {
-            Request::ChunkRange { start, end } => {
-                for chunk_pos in iter_3d_vec(start, end) {
-                    let mut chunk = Chunk::new(chunk_pos);
-                    world_gen::gen_blocks(&mut chunk, chunk_pos);
-                    let response = Response::ChunkData(chunk);
-                    protocol.send(&response)?
-                }
+        let request = match protocol.read() {
+            Ok(request) => request,
+            Err(err) => match invalid_frame(&err) {
+                Some(reason) => return Err(queue_disconnect(&sender, reason)),
+                None => return Err(err),
+            },
+        };
+        log::debug!("Received message: {:#?}", request);
+
+        if let Request::Hello { .. } = request {
+            return Err(queue_disconnect(&sender, "Already said hello".to_owned()));
+        }
+
+        if context
+            .events
+            .send(ClientEvent::Request { addr, request })
+            .is_err()
+        {
+            return Err(queue_disconnect(&sender, "Server stopped".to_owned()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use network::{Protocol, Request, Response};
+    use opencuboids_common::{block_to_chunk_pos, block_to_local_pos, BlockID, Light, AIR};
+
+    fn start_test_server(name: &str) -> SocketAddr {
+        start_test_world(name).0
+    }
+
+    /// Starts a server keeping the world so the test can check what's loaded
+    fn start_test_world(name: &str) -> (SocketAddr, Arc<World>) {
+        let world_dir = world_save::temp_world_dir(name);
+        let address = "127.0.0.1:0".parse().unwrap();
+        let server = Server::bind(address, world_dir, BlockRegistry::default()).unwrap();
+        let address = server.local_addr().unwrap();
+        let world = server.world.clone();
+        std::thread::spawn(move || server.run());
+        (address, world)
+    }
+
+    /// Waits for the simulation to catch up with requests that don't get a response
+    fn wait_until(condition: impl Fn() -> bool) {
+        let start = std::time::Instant::now();
+        while !condition() {
+            assert!(start.elapsed() < std::time::Duration::from_secs(5));
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn handshake_accepted() {
+        let address = start_test_server("handshake-accepted");
+        let mut protocol = Protocol::connect(address, 0).unwrap();
+        let (info, registry) = protocol.handshake("tester").unwrap();
+        assert_eq!(info.tick_rate, DEFAULT_TICK_RATE);
+        assert_eq!(registry, BlockRegistry::default());
+
+        // High above the highest the terrain goes
+        let sky_chunk = glam::ivec3(0, 4, 0);
+        protocol
+            .send(&Request::ChunkRange {
+                start: sky_chunk,
+                end: sky_chunk + 1,
+            })
+            .unwrap();
+        // Chunks come lit with nothing above them blocking the sky, after the biomes of the column
+        assert!(matches!(
+            protocol.read::<Response>().unwrap(),
+            Response::ColumnBiomes { column, .. } if column == glam::ivec2(0, 0)
+        ));
+        assert!(matches!(
+            protocol.read::<Response>().unwrap(),
+            Response::ChunkData(chunk) if chunk.pos == sky_chunk
+                && chunk.get_light(glam::uvec3(0, 31, 0)) == Light::SKY
+        ));
+    }
+
+    #[test]
+    fn handshake_rejected() {
+        let address = start_test_server("handshake-rejected");
+
+        let mut protocol = Protocol::connect(address, 0).unwrap();
+        protocol
+            .send(&Request::Hello {
+                protocol_version: network::PROTOCOL_VERSION + 1,
+                player_name: "tester".to_owned(),
+            })
+            .unwrap();
+        assert!(matches!(
+            protocol.read::<Response>().unwrap(),
+            Response::Disconnect(reason) if reason.contains("protocol version")
+        ));
+
+        let mut protocol = Protocol::connect(address, 0).unwrap();
+        let err = protocol.handshake("").unwrap_err();
+        assert!(err.to_string().contains("Player name"));
+
+        // The length is in characters rather than bytes
+        let long_name = "é".repeat(network::MAX_PLAYER_NAME_LEN + 1);
+        let mut protocol = Protocol::connect(address, 0).unwrap();
+        let err = protocol.handshake(&long_name).unwrap_err();
+        assert!(err.to_string().contains("Player name"));
+        let mut protocol = Protocol::connect(address, 0).unwrap();
+        protocol.handshake(&long_name[2..]).unwrap();
+
+        let mut protocol = Protocol::connect(address, 0).unwrap();
+        protocol
+            .send(&Request::ChunkRange {
+                start: glam::IVec3::ZERO,
+                end: glam::IVec3::ONE,
+            })
+            .unwrap();
+        assert!(matches!(
+            protocol.read::<Response>().unwrap(),
+            Response::Disconnect(_)
+        ));
+    }
+
+    #[test]
+    fn block_updates_broadcast() {
+        let address = start_test_server("block-updates");
+        let mut clients = ["first", "second"].map(|name| {
+            let mut protocol = Protocol::connect(address, 0).unwrap();
+            let (info, _) = protocol.handshake(name).unwrap();
+            (protocol, info.spawn_position)
+        });
+
+        // The block under the player at spawn
+        let pos = clients[0].1.floor().as_ivec3() - glam::IVec3::Y;
+        let chunk_pos = block_to_chunk_pos(pos);
+        let local_pos = block_to_local_pos(pos);
+        let chunks = clients.each_mut().map(|(protocol, _)| {
+            protocol
+                .send(&Request::ChunkRange {
+                    start: chunk_pos,
+                    end: chunk_pos + 1,
+                })
+                .unwrap();
+            read_until(protocol, |response| match response {
+                Response::ChunkData(chunk) => Some(chunk),
+                _ => None,
+            })
+        });
+
+        // Break the block if there is one otherwise place stone
+        let id = match chunks[0].get_block(local_pos) {
+            AIR => BlockRegistry::default().id("stone").unwrap(),
+            _ => AIR,
+        };
+
+        let protocol = &mut clients[0].0;
+        // Out of reach and unknown blocks get ignored
+        let far_pos = pos - glam::ivec3(0, 20, 0);
+        protocol
+            .send(&Request::SetBlock { pos: far_pos, id })
+            .unwrap();
+        protocol
+            .send(&Request::SetBlock {
+                pos,
+                id: BlockID::MAX,
+            })
+            .unwrap();
+        protocol.send(&Request::SetBlock { pos, id }).unwrap();
+
+        // The other player can get spawned in before the update
+        for (protocol, _) in &mut clients {
+            let update = read_until(protocol, |response| match response {
+                Response::SpawnEntity { .. } => None,
+                response => Some(response),
+            });
+            assert!(matches!(
+                update,
+                Response::BlockUpdate { pos: update_pos, id: update_id }
+                    if update_pos == pos && update_id == id
+            ));
+
+            // Followed by the new light of the block instead of the whole chunk again
+            assert!(matches!(
+                protocol.read::<Response>().unwrap(),
+                Response::LightUpdate(lights) if lights.iter().any(|(light_pos, _)| *light_pos == pos)
+            ));
+        }
+    }
+
+    /// Reads responses until one gets matched, skipping the others
+    fn read_until<T>(protocol: &mut Protocol, matches: impl Fn(Response) -> Option<T>) -> T {
+        loop {
+            if let Some(value) = matches(protocol.read().unwrap()) {
+                return value;
             }
         }
     }
+
+    #[test]
+    fn players_sent_as_entities() {
+        let address = start_test_server("player-entities");
+        let mut clients = ["first", "second"].map(|name| {
+            let mut protocol = Protocol::connect(address, 0).unwrap();
+            let (info, _) = protocol.handshake(name).unwrap();
+            (protocol, info.spawn_position)
+        });
+
+        let spawn_position = clients[0].1;
+        let chunk_pos = block_to_chunk_pos(spawn_position.floor().as_ivec3());
+        for (protocol, _) in &mut clients {
+            protocol
+                .send(&Request::ChunkRange {
+                    start: chunk_pos,
+                    end: chunk_pos + 1,
+                })
+                .unwrap();
+        }
+
+        // Players get spawned on the clients with the chunk they're in loaded
+        let [(mut first, _), (mut second, _)] = clients;
+        let first_id = read_until(&mut second, |response| match response {
+            Response::SpawnEntity { id, name, .. } if name == "first" => Some(id),
+            _ => None,
+        });
+
+        // Staying inside the spawn block column so nothing around it can get in the way
+        let position = spawn_position + glam::vec3(0.1, 0.0, 0.1);
+        let rotation = glam::vec2(90.0, -10.0);
+        first
+            .send(&Request::PlayerTransform { position, rotation })
+            .unwrap();
+        let moved = read_until(&mut second, |response| match response {
+            Response::MoveEntity {
+                id,
+                position,
+                rotation,
+            } if id == first_id => Some((position, rotation)),
+            _ => None,
+        });
+        assert_eq!(moved, (position, rotation));
+
+        drop(first);
+        read_until(&mut second, |response| {
+            matches!(response, Response::DespawnEntity(id) if id == first_id).then_some(())
+        });
+    }
+
+    #[test]
+    fn teleport_corrected() {
+        let address = start_test_server("teleport");
+        let mut protocol = Protocol::connect(address, 0).unwrap();
+        let (info, _) = protocol.handshake("tester").unwrap();
+
+        let position = info.spawn_position + glam::vec3(1000.0, 0.0, 0.0);
+        protocol
+            .send(&Request::PlayerTransform {
+                position,
+                rotation: glam::Vec2::ZERO,
+            })
+            .unwrap();
+        assert!(matches!(
+            protocol.read::<Response>().unwrap(),
+            Response::SetPosition { position } if position == info.spawn_position
+        ));
+    }
+
+    #[test]
+    fn chunks_shared_between_players() {
+        let (address, world) = start_test_world("shared-chunks");
+        let mut clients = ["first", "second"].map(|name| {
+            let mut protocol = Protocol::connect(address, 0).unwrap();
+            protocol.handshake(name).unwrap();
+            protocol
+        });
+
+        // Both players get the same chunk which only gets generated once
+        let chunk_pos = glam::ivec3(1, 0, -1);
+        let chunks = clients.each_mut().map(|protocol| {
+            protocol
+                .send(&Request::ChunkRange {
+                    start: chunk_pos,
+                    end: chunk_pos + 1,
+                })
+                .unwrap();
+            read_until(protocol, |response| match response {
+                Response::ChunkData(chunk) => Some(bincode::serialize(&chunk).unwrap()),
+                _ => None,
+            })
+        });
+        assert_eq!(chunks[0], chunks[1]);
+        assert_eq!(world.watchers(chunk_pos), 2);
+
+        // Unloading it or leaving stops the player watching it
+        let [mut first, second] = clients;
+        first.send(&Request::UnloadChunks(vec![chunk_pos])).unwrap();
+        wait_until(|| world.watchers(chunk_pos) == 1);
+        drop(second);
+        wait_until(|| world.watchers(chunk_pos) == 0);
+
+        // Then gets unloaded once no one has watched it for a while
+        assert!(world.get_chunk(chunk_pos).is_some());
+        world.unload_unwatched(std::time::Instant::now() + world::UNLOAD_TIMEOUT);
+        assert!(world.get_chunk(chunk_pos).is_none());
+    }
+
+    #[test]
+    fn far_chunks_disconnect() {
+        let address = start_test_server("far-chunks");
+        let mut protocol = Protocol::connect(address, 0).unwrap();
+        protocol.handshake("tester").unwrap();
+
+        // Would take forever to go through if it didn't get rejected
+        protocol
+            .send(&Request::ChunkRange {
+                start: glam::IVec3::splat(i32::MIN),
+                end: glam::IVec3::splat(i32::MAX),
+            })
+            .unwrap();
+        assert!(matches!(
+            protocol.read::<Response>().unwrap(),
+            Response::Disconnect(reason) if reason.contains("chunks away")
+        ));
+        assert!(protocol.read::<Response>().is_err());
+    }
+
+    #[test]
+    fn invalid_frame_disconnects() {
+        use std::io::Write;
+
+        let address = start_test_server("invalid-frame");
+        let mut protocol = Protocol::connect(address, 0).unwrap();
+        protocol.handshake("tester").unwrap();
+        protocol.stream.write_all(&u32::MAX.to_le_bytes()).unwrap();
+        assert!(matches!(
+            protocol.read::<Response>().unwrap(),
+            Response::Disconnect(reason) if reason.contains("Invalid request")
+        ));
+    }
 }
@@ -0,0 +1,295 @@
+use opencuboids_common::{iter_3d_vec, PLAYER_BOX_MAX, PLAYER_BOX_MIN};
+
+/// Keeps boxes that are touching a block face from counting as inside it
+const EPSILON: f32 = 1e-3;
+/// Most seconds of movement a single move can use, a few times how often clients send their
+/// position so players that stop sending for a while don't build up a teleport
+const MAX_MOVE_TIME: f32 = 0.25;
+
+/// How far players are allowed to move, moves past these get corrected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MovementLimits {
+    /// Blocks per second a player can move in any direction
+    pub max_speed: f32,
+    /// Extra blocks players can get ahead of the speed by so lag doesn't get them corrected,
+    /// it only comes back by moving slower than the speed
+    pub tolerance: f32,
+}
+
+impl Default for MovementLimits {
+    fn default() -> Self {
+        Self {
+            max_speed: 40.0,
+            tolerance: 2.0,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum InvalidMove {
+    NotFinite,
+    TooFast { distance: f32, allowed: f32 },
+    InsideBlock(glam::IVec3),
+}
+
+impl std::fmt::Display for InvalidMove {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFinite => write!(f, "the position isn't a finite number"),
+            Self::TooFast { distance, allowed } => write!(
+                f,
+                "moved {:.2} blocks when only {:.2} were allowed",
+                distance, allowed
+            ),
+            Self::InsideBlock(pos) => write!(f, "the player would be inside the block at {}", pos),
+        }
+    }
+}
+
+/// Checks the positions a player reports against where it was last allowed to be
+pub struct MovementValidator {
+    limits: MovementLimits,
+    tick_rate: u32,
+    /// The last position that was accepted
+    position: glam::Vec3,
+    /// The tick the position was accepted on
+    tick: u64,
+    /// How far the player can still move without any more ticks passing
+    slack: f32,
+}
+
+impl MovementValidator {
+    pub fn new(limits: MovementLimits, tick_rate: u32, position: glam::Vec3, tick: u64) -> Self {
+        Self {
+            limits,
+            tick_rate,
+            position,
+            tick,
+            slack: limits.tolerance,
+        }
+    }
+
+    pub fn position(&self) -> glam::Vec3 {
+        self.position
+    }
+
+    /// Accepts the move if the player could have got there since the last accepted move
+    /// without ending up inside of a solid block
+    ///
+    /// Players that were already inside of blocks, like when one gets placed on them, can move
+    /// through blocks until they get out.
+    pub fn check(
+        &mut self,
+        position: glam::Vec3,
+        tick: u64,
+        is_solid: impl Fn(glam::IVec3) -> bool,
+    ) -> Result<(), InvalidMove> {
+        if !position.is_finite() {
+            return Err(InvalidMove::NotFinite);
+        }
+
+        // Moves that arrive early or several in one tick have to fit in what's left, only up to
+        // the tolerance carries over into later ticks so it can't be used up again on every move
+        let max_ticks = (MAX_MOVE_TIME * self.tick_rate as f32).ceil() as u64;
+        let ticks = tick.saturating_sub(self.tick).min(max_ticks);
+        let allowed = if ticks == 0 {
+            self.slack
+        } else {
+            self.limits.max_speed * ticks as f32 / self.tick_rate as f32
+                + self.slack.min(self.limits.tolerance)
+        };
+        let distance = position.distance(self.position);
+        if distance > allowed {
+            return Err(InvalidMove::TooFast { distance, allowed });
+        }
+
+        if solid_block_inside(self.position, &is_solid).is_none() {
+            if let Some(block_pos) = solid_block_inside(position, &is_solid) {
+                return Err(InvalidMove::InsideBlock(block_pos));
+            }
+        }
+
+        self.position = position;
+        self.tick = tick;
+        self.slack = allowed - distance;
+        Ok(())
+    }
+}
+
+/// Gets the first solid block the player box at the position is inside of
+fn solid_block_inside(
+    position: glam::Vec3,
+    is_solid: impl Fn(glam::IVec3) -> bool,
+) -> Option<glam::IVec3> {
+    let min = (position + PLAYER_BOX_MIN + EPSILON).floor().as_ivec3();
+    let max = (position + PLAYER_BOX_MAX - EPSILON).floor().as_ivec3();
+    iter_3d_vec(min, max + 1).find(|pos| is_solid(*pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use opencuboids_common::MAX_FALL_SPEED;
+
+    use super::*;
+
+    const LIMITS: MovementLimits = MovementLimits {
+        max_speed: 10.0,
+        tolerance: 0.5,
+    };
+
+    /// Flat ground with the top at y 0 and a pillar at x 3
+    fn is_solid(pos: glam::IVec3) -> bool {
+        pos.y < 0 || (pos.x == 3 && pos.z == 0)
+    }
+
+    #[test]
+    fn speed_limited() {
+        let start = glam::vec3(0.5, 1.6, 0.5);
+        let mut validator = MovementValidator::new(LIMITS, 20, start, 0);
+
+        // Half a block a tick then a full block after waiting a tick
+        let moves = [
+            (glam::vec3(0.5, 1.6, 1.0), 1),
+            (glam::vec3(0.5, 1.6, 1.5), 2),
+            (glam::vec3(0.5, 1.6, 2.5), 4),
+        ];
+        for (position, tick) in moves {
+            assert_eq!(validator.check(position, tick, is_solid), Ok(()));
+        }
+
+        // Teleporting gets rejected and the player stays where it was
+        let last = validator.position();
+        assert!(matches!(
+            validator.check(glam::vec3(100.0, 1.6, 0.5), 5, is_solid),
+            Err(InvalidMove::TooFast { .. })
+        ));
+        assert_eq!(
+            validator.check(glam::vec3(f32::NAN, 1.6, 0.5), 5, is_solid),
+            Err(InvalidMove::NotFinite)
+        );
+        assert_eq!(validator.position(), last);
+
+        // Far enough once enough ticks have passed since the last accepted move
+        assert_eq!(
+            validator.check(last + glam::vec3(0.0, 0.0, 2.5), 8, is_solid),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn moves_in_one_tick_limited() {
+        let start = glam::vec3(0.5, 1.6, 0.5);
+        let mut validator = MovementValidator::new(LIMITS, 20, start, 0);
+
+        // A tick of movement and the tolerance get shared between all of the moves
+        for i in 1..=3 {
+            let position = start + glam::vec3(0.0, 0.0, 0.3 * i as f32);
+            assert_eq!(validator.check(position, 1, is_solid), Ok(()));
+        }
+        assert!(matches!(
+            validator.check(start + glam::vec3(0.0, 0.0, 1.2), 1, is_solid),
+            Err(InvalidMove::TooFast { .. })
+        ));
+    }
+
+    #[test]
+    fn tolerance_used_up() {
+        let start = glam::vec3(0.5, 1.6, 0.5);
+
+        // Just under the speed can keep going forever
+        let mut validator = MovementValidator::new(LIMITS, 20, start, 0);
+        for tick in 1..=100 {
+            let position = start + glam::vec3(0.0, 0.0, 0.49 * tick as f32);
+            assert_eq!(validator.check(position, tick, is_solid), Ok(()));
+        }
+
+        // Going a bit faster only works until the tolerance runs out
+        let mut validator = MovementValidator::new(LIMITS, 20, start, 0);
+        for tick in 1..=4 {
+            let position = start + glam::vec3(0.0, 0.0, 0.6 * tick as f32);
+            assert_eq!(validator.check(position, tick, is_solid), Ok(()));
+        }
+        assert!(matches!(
+            validator.check(start + glam::vec3(0.0, 0.0, 3.6), 6, is_solid),
+            Err(InvalidMove::TooFast { .. })
+        ));
+
+        // Waiting for the ticks to catch up lets it carry on
+        let position = validator.position() + glam::vec3(0.0, 0.0, 1.2);
+        assert_eq!(validator.check(position, 7, is_solid), Ok(()));
+    }
+
+    #[test]
+    fn long_falls_allowed() {
+        // Falling as fast as the client does while walking, sent about every 60ms so some moves
+        // get two ticks and others one
+        let start = glam::vec3(0.5, 1000.0, 0.5);
+        let mut validator = MovementValidator::new(MovementLimits::default(), 20, start, 0);
+        for i in 1..300 {
+            let seconds = i as f32 * 0.06;
+            let offset = glam::vec3(10.0, -MAX_FALL_SPEED, 0.0) * seconds;
+            let tick = (seconds * 20.0) as u64;
+            assert_eq!(validator.check(start + offset, tick, |_| false), Ok(()));
+        }
+    }
+
+    #[test]
+    fn waiting_builds_no_teleport() {
+        let start = glam::vec3(0.5, 1.6, 0.5);
+        let mut validator = MovementValidator::new(LIMITS, 20, start, 0);
+
+        // Standing still for half a minute then jumping far away
+        for tick in 1..600 {
+            assert_eq!(validator.check(start, tick, is_solid), Ok(()));
+        }
+        assert!(matches!(
+            validator.check(start + glam::vec3(0.0, 0.0, 50.0), 600, is_solid),
+            Err(InvalidMove::TooFast { .. })
+        ));
+
+        // Not sending anything for a while doesn't help either
+        assert!(matches!(
+            validator.check(start + glam::vec3(0.0, 0.0, 50.0), 1200, is_solid),
+            Err(InvalidMove::TooFast { .. })
+        ));
+        assert_eq!(
+            validator.check(start + glam::vec3(0.0, 0.0, 3.0), 1200, is_solid),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn blocks_stop_moves() {
+        let start = glam::vec3(1.5, 1.6, 0.5);
+        let mut validator = MovementValidator::new(LIMITS, 20, start, 0);
+
+        // Standing right up against the pillar is fine
+        assert_eq!(
+            validator.check(glam::vec3(2.7, 1.6, 0.5), 2, is_solid),
+            Ok(())
+        );
+        assert_eq!(
+            validator.check(glam::vec3(3.1, 1.6, 0.5), 3, is_solid),
+            Err(InvalidMove::InsideBlock(glam::ivec3(3, 0, 0)))
+        );
+        assert!(matches!(
+            validator.check(glam::vec3(2.7, 1.0, 0.5), 3, is_solid),
+            Err(InvalidMove::InsideBlock(pos)) if pos.y == -1
+        ));
+
+        // Players stuck in blocks can move out of them
+        let mut validator = MovementValidator::new(LIMITS, 20, glam::vec3(3.5, 1.6, 0.5), 0);
+        let moves = [
+            (glam::vec3(3.5, 1.6, 0.9), 1),
+            (glam::vec3(3.5, 1.6, 1.4), 2),
+            (glam::vec3(3.5, 1.6, 1.5), 3),
+        ];
+        for (position, tick) in moves {
+            assert_eq!(validator.check(position, tick, is_solid), Ok(()));
+        }
+        assert!(matches!(
+            validator.check(glam::vec3(3.5, 1.6, 0.9), 4, is_solid),
+            Err(InvalidMove::InsideBlock(_))
+        ));
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+/// Amount of chunks along each axis stored in a single region file
+pub const REGION_SIZE: i32 = 16;
+pub const REGION_VOLUME: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+
+/// Bump whenever the layout of the region file or the encoding of the chunks changes
+pub const FORMAT_VERSION: u32 = 4;
+const MAGIC: &[u8; 4] = b"OCRG";
+
+/// Magic, version then an offset and length for every chunk in the region
+const HEADER_SIZE: u64 = 4 + 4 + REGION_VOLUME as u64 * 8;
+
+#[derive(Clone, Copy, Default)]
+struct Entry {
+    offset: u32,
+    length: u32,
+}
+
+/// A file storing the encoded data of up to REGION_VOLUME chunks
+///
+/// Layout:
+/// | magic | version | offset table | chunk data ... |
+/// Chunk data is appended to the end of the file unless it fits into the old slot
+pub struct RegionFile {
+    file: File,
+    entries: Vec<Entry>,
+}
+
+impl RegionFile {
+    /// Opens the region file at the path, creating a new empty one if it doesn't exist
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        if file.metadata()?.len() == 0 {
+            let mut region = Self {
+                file,
+                entries: vec![Entry::default(); REGION_VOLUME],
+            };
+            region.write_header()?;
+            return Ok(region);
+        }
+
+        let mut header = vec![0; HEADER_SIZE as usize];
+        file.read_exact(&mut header)?;
+
+        if &header[0..4] != MAGIC {
+            return Err(invalid_data("not a region file"));
+        }
+
+        let version = read_u32(&header[4..8]);
+        if version != FORMAT_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported region format version {} (expected {})",
+                version, FORMAT_VERSION
+            )));
+        }
+
+        let entries = header[8..]
+            .chunks_exact(8)
+            .map(|entry| Entry {
+                offset: read_u32(&entry[0..4]),
+                length: read_u32(&entry[4..8]),
+            })
+            .collect();
+
+        Ok(Self { file, entries })
+    }
+
+    /// Returns the encoded data of the chunk at the index or None if it was never written
+    pub fn read(&mut self, index: usize) -> std::io::Result<Option<Vec<u8>>> {
+        let entry = self.entries[index];
+        if entry.length == 0 {
+            return Ok(None);
+        }
+
+        let mut data = vec![0; entry.length as usize];
+        self.file.seek(SeekFrom::Start(entry.offset as u64))?;
+        self.file.read_exact(&mut data)?;
+        Ok(Some(data))
+    }
+
+    pub fn write(&mut self, index: usize, data: &[u8]) -> std::io::Result<()> {
+        let old_entry = self.entries[index];
+        let offset = if old_entry.length as usize >= data.len() {
+            old_entry.offset as u64
+        } else {
+            self.file.seek(SeekFrom::End(0))?
+        };
+
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(data)?;
+
+        let entry = Entry {
+            offset: offset as u32,
+            length: data.len() as u32,
+        };
+        self.entries[index] = entry;
+
+        // Only update the chunk's entry in the offset table
+        self.file.seek(SeekFrom::Start(8 + index as u64 * 8))?;
+        self.file.write_all(&entry.offset.to_le_bytes())?;
+        self.file.write_all(&entry.length.to_le_bytes())?;
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        self.file.sync_data()
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        for entry in &self.entries {
+            header.extend_from_slice(&entry.offset.to_le_bytes());
+            header.extend_from_slice(&entry.length.to_le_bytes());
+        }
+
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)
+    }
+}
+
+/// Gets the position of the region containing the chunk
+pub fn region_pos(chunk_pos: glam::IVec3) -> glam::IVec3 {
+    glam::ivec3(
+        chunk_pos.x.div_euclid(REGION_SIZE),
+        chunk_pos.y.div_euclid(REGION_SIZE),
+        chunk_pos.z.div_euclid(REGION_SIZE),
+    )
+}
+
+/// Gets the index of the chunk inside of its region's offset table
+pub fn region_index(chunk_pos: glam::IVec3) -> usize {
+    let local = glam::ivec3(
+        chunk_pos.x.rem_euclid(REGION_SIZE),
+        chunk_pos.y.rem_euclid(REGION_SIZE),
+        chunk_pos.z.rem_euclid(REGION_SIZE),
+    );
+    (local.y * REGION_SIZE * REGION_SIZE + local.z * REGION_SIZE + local.x) as usize
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes.try_into().unwrap())
+}
+
+fn invalid_data(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_region_path(name: &str) -> std::path::PathBuf {
+        let dir = crate::world_save::temp_world_dir(&format!("region-{}", name));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("r.0.0.0.ocr")
+    }
+
+    #[test]
+    fn new_file_has_header() {
+        let path = temp_region_path("header");
+        let mut region = RegionFile::open(&path).unwrap();
+        assert_eq!(region.read(0).unwrap(), None);
+        drop(region);
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(data.len() as u64, HEADER_SIZE);
+        assert_eq!(&data[0..4], MAGIC);
+        assert_eq!(read_u32(&data[4..8]), FORMAT_VERSION);
+        assert!(data[8..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn offset_table_written() {
+        let path = temp_region_path("offsets");
+        let mut region = RegionFile::open(&path).unwrap();
+        region.write(3, &[1, 2, 3]).unwrap();
+        region.write(REGION_VOLUME - 1, &[4, 5]).unwrap();
+        drop(region);
+
+        let data = std::fs::read(&path).unwrap();
+        let entry = |index: usize| {
+            let start = 8 + index * 8;
+            (
+                read_u32(&data[start..start + 4]),
+                read_u32(&data[start + 4..start + 8]),
+            )
+        };
+        assert_eq!(entry(3), (HEADER_SIZE as u32, 3));
+        assert_eq!(entry(REGION_VOLUME - 1), (HEADER_SIZE as u32 + 3, 2));
+        assert_eq!(entry(0), (0, 0));
+        assert_eq!(data[HEADER_SIZE as usize..], [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn chunk_rewritten() {
+        let path = temp_region_path("rewrite");
+        let mut region = RegionFile::open(&path).unwrap();
+        region.write(7, &[1, 2, 3]).unwrap();
+        region.write(8, &[9]).unwrap();
+
+        // Smaller data reuses the old slot, bigger data gets appended
+        region.write(7, &[4, 5]).unwrap();
+        assert_eq!(region.read(7).unwrap(), Some(vec![4, 5]));
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), HEADER_SIZE + 3 + 1);
+        region.write(7, &[6, 7, 8, 9]).unwrap();
+        assert_eq!(region.read(7).unwrap(), Some(vec![6, 7, 8, 9]));
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            HEADER_SIZE + 3 + 1 + 4
+        );
+        assert_eq!(region.read(8).unwrap(), Some(vec![9]));
+    }
+
+    #[test]
+    fn loaded_after_reopen() {
+        let path = temp_region_path("reopen");
+        let mut region = RegionFile::open(&path).unwrap();
+        region.write(0, &[1, 2, 3]).unwrap();
+        region.write(100, &[4]).unwrap();
+        region.write(0, &[5, 6, 7, 8]).unwrap();
+        region.sync().unwrap();
+        drop(region);
+
+        let mut region = RegionFile::open(&path).unwrap();
+        assert_eq!(region.read(0).unwrap(), Some(vec![5, 6, 7, 8]));
+        assert_eq!(region.read(100).unwrap(), Some(vec![4]));
+        assert_eq!(region.read(1).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let path = temp_region_path("invalid");
+        std::fs::write(&path, vec![0; HEADER_SIZE as usize]).unwrap();
+        assert!(RegionFile::open(&path).is_err());
+
+        let mut header = vec![0; HEADER_SIZE as usize];
+        header[0..4].copy_from_slice(MAGIC);
+        header[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        std::fs::write(&path, header).unwrap();
+        assert!(RegionFile::open(&path).is_err());
+    }
+
+    #[test]
+    fn negative_chunks_in_own_region() {
+        assert_eq!(region_pos(glam::ivec3(-1, 0, 15)), glam::ivec3(-1, 0, 0));
+        assert_eq!(
+            region_pos(glam::ivec3(-16, -17, 16)),
+            glam::ivec3(-1, -2, 1)
+        );
+        assert_eq!(region_index(glam::ivec3(-1, 0, 0)), 15);
+        assert_eq!(region_index(glam::ivec3(0, -1, 0)), REGION_VOLUME - 256);
+        assert_eq!(region_index(glam::ivec3(16, 16, 16)), 0);
+    }
+}
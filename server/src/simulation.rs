@@ -0,0 +1,888 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    net::SocketAddr,
+    sync::{mpsc, Arc},
+    time::{Duration, Instant},
+};
+
+use opencuboids_common::{
+    block_to_chunk_pos, block_to_local_pos, iter_3d_vec,
+    network::{EntityID, Request, Response},
+    update_light, BlockID, Chunk, LightChanges, AIR,
+};
+
+use crate::{
+    generator::ChunkGenerator,
+    movement::{MovementLimits, MovementValidator},
+    world::World,
+    DEFAULT_TICK_RATE,
+};
+
+/// How far away from the player a block can be changed
+const MAX_REACH: f32 = 8.0;
+/// How many chunks away from the chunk the player is in chunks can be requested, well past the
+/// render distance of the client since it can be ahead of its last accepted position
+const MAX_CHUNK_DISTANCE: i32 = 16;
+/// Ticks it takes a block with nothing under it to fall down by one block
+const FALL_DELAY: u64 = 2;
+
+/// Sent from the client threads to the simulation
+pub enum ClientEvent {
+    Joined {
+        addr: SocketAddr,
+        player_name: String,
+        /// Gets the responses to write to the client
+        sender: mpsc::Sender<Response>,
+    },
+    Request {
+        addr: SocketAddr,
+        request: Request,
+    },
+    Left {
+        addr: SocketAddr,
+    },
+}
+
+struct Client {
+    player_name: String,
+    sender: mpsc::Sender<Response>,
+    /// Chunks that have been sent to the client
+    loaded_chunks: HashSet<glam::IVec3>,
+    /// Amount of loaded chunks in each column, the biomes get sent with the first one
+    loaded_columns: HashMap<glam::IVec2, usize>,
+    /// The player is sent to the other clients as this entity
+    entity_id: EntityID,
+    /// Has the last position of the player that was allowed
+    movement: MovementValidator,
+    rotation: glam::Vec2,
+    /// Whether the player has moved since the last tick
+    moved: bool,
+    /// Entities of the other players that have been spawned on the client
+    spawned_entities: HashSet<EntityID>,
+    /// Responses that get sent at the end of the tick
+    outbox: Vec<Response>,
+}
+
+/// Owns the world and updates it at a fixed rate, the client threads only pass on what their
+/// clients send and receive
+pub struct Simulation {
+    world: Arc<World>,
+    spawn_position: glam::Vec3,
+    tick_rate: u32,
+    movement_limits: MovementLimits,
+    events: mpsc::Receiver<ClientEvent>,
+    clients: HashMap<SocketAddr, Client>,
+    /// Ticks since the simulation started
+    tick: u64,
+    next_entity_id: EntityID,
+    generator: ChunkGenerator,
+    /// Chunks being generated with the clients waiting on them
+    waiting: HashMap<glam::IVec3, HashSet<SocketAddr>>,
+    /// Blocks to update keyed by the tick they get updated on
+    scheduled_updates: BTreeMap<u64, HashSet<glam::IVec3>>,
+}
+
+impl Simulation {
+    pub fn new(
+        world: Arc<World>,
+        spawn_position: glam::Vec3,
+        events: mpsc::Receiver<ClientEvent>,
+    ) -> Self {
+        Self {
+            generator: ChunkGenerator::new(world.clone()),
+            world,
+            spawn_position,
+            tick_rate: DEFAULT_TICK_RATE,
+            movement_limits: MovementLimits::default(),
+            events,
+            clients: HashMap::new(),
+            tick: 0,
+            next_entity_id: 0,
+            waiting: HashMap::new(),
+            scheduled_updates: BTreeMap::new(),
+        }
+    }
+
+    pub fn set_tick_rate(&mut self, tick_rate: u32) {
+        self.tick_rate = tick_rate;
+    }
+
+    pub fn set_movement_limits(&mut self, limits: MovementLimits) {
+        self.movement_limits = limits;
+    }
+
+    /// Ticks until every client thread and the server have stopped
+    pub fn run(mut self) {
+        let tick_duration = Duration::from_secs(1) / self.tick_rate;
+        let mut clock = TickClock::new(tick_duration, Instant::now());
+
+        // Leave half of each tick for everything other than adding generated chunks
+        while self.tick(tick_duration / 2) {
+            match clock.advance(Instant::now()) {
+                TickWait::Sleep(duration) => std::thread::sleep(duration),
+                TickWait::Overrun { late, skipped } => {
+                    log::warn!(
+                        "Tick {} ran {}ms over, skipping {} ticks to catch up",
+                        self.tick,
+                        late.as_millis(),
+                        skipped
+                    );
+                    self.skip_ticks(skipped.into());
+                }
+            }
+        }
+    }
+
+    /// Counts ticks that didn't get run so moves get checked against the time that really passed,
+    /// the blocks scheduled for them get updated on the next tick
+    fn skip_ticks(&mut self, ticks: u64) {
+        self.tick += ticks;
+    }
+
+    /// Runs a single tick, spending at most around the budget on adding generated chunks
+    ///
+    /// Returns false once nothing can send events anymore.
+    pub fn tick(&mut self, chunk_budget: Duration) -> bool {
+        let start = Instant::now();
+
+        loop {
+            match self.events.try_recv() {
+                Ok(event) => self.handle_event(event),
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => return false,
+            }
+        }
+
+        self.add_generated_chunks(start + chunk_budget);
+        self.run_block_updates();
+        self.update_entities();
+        let unloaded = self.world.unload_unwatched(Instant::now());
+        if unloaded > 0 {
+            log::debug!("Unloaded {} chunks that no one was watching", unloaded);
+        }
+
+        for client in self.clients.values_mut() {
+            for response in client.outbox.drain(..) {
+                // The client is disconnecting if its thread stopped receiving
+                let _ = client.sender.send(response);
+            }
+        }
+
+        self.tick += 1;
+        true
+    }
+
+    fn handle_event(&mut self, event: ClientEvent) {
+        match event {
+            ClientEvent::Joined {
+                addr,
+                player_name,
+                sender,
+            } => {
+                let client = Client {
+                    player_name,
+                    sender,
+                    loaded_chunks: HashSet::new(),
+                    loaded_columns: HashMap::new(),
+                    entity_id: self.next_entity_id,
+                    movement: MovementValidator::new(
+                        self.movement_limits,
+                        self.tick_rate,
+                        self.spawn_position,
+                        self.tick,
+                    ),
+                    rotation: glam::Vec2::ZERO,
+                    moved: false,
+                    spawned_entities: HashSet::new(),
+                    outbox: Vec::new(),
+                };
+                self.next_entity_id += 1;
+                self.clients.insert(addr, client);
+            }
+            ClientEvent::Request { addr, request } => self.handle_request(addr, request),
+            ClientEvent::Left { addr } => {
+                let Some(client) = self.clients.remove(&addr) else {
+                    return;
+                };
+
+                for chunk_pos in client.loaded_chunks {
+                    self.world.unwatch(chunk_pos);
+                }
+
+                for other in self.clients.values_mut() {
+                    if other.spawned_entities.remove(&client.entity_id) {
+                        other.outbox.push(Response::DespawnEntity(client.entity_id));
+                    }
+                }
+
+                let waiting_chunks = self
+                    .waiting
+                    .iter()
+                    .filter(|(_, waiting)| waiting.contains(&addr))
+                    .map(|(chunk_pos, _)| *chunk_pos)
+                    .collect::<Vec<_>>();
+                for chunk_pos in waiting_chunks {
+                    self.stop_waiting(addr, chunk_pos);
+                }
+            }
+        }
+    }
+
+    fn handle_request(&mut self, addr: SocketAddr, request: Request) {
+        let Some(client) = self.clients.get_mut(&addr) else {
+            return;
+        };
+
+        match request {
+            // Only the client thread checks for it
+            Request::Hello { .. } => (),
+            Request::ChunkRange { start, end } => {
+                let player_chunk_pos =
+                    block_to_chunk_pos(client.movement.position().floor().as_ivec3());
+                if start.cmplt(player_chunk_pos - MAX_CHUNK_DISTANCE).any()
+                    || end.cmpgt(player_chunk_pos + MAX_CHUNK_DISTANCE + 1).any()
+                {
+                    log::warn!(
+                        "{} requested the chunks from {} to {} while in the chunk {}, disconnecting",
+                        client.player_name,
+                        start,
+                        end,
+                        player_chunk_pos
+                    );
+                    client.outbox.push(Response::Disconnect(format!(
+                        "Chunks can only be requested up to {} chunks away",
+                        MAX_CHUNK_DISTANCE
+                    )));
+                    return;
+                }
+
+                for chunk_pos in iter_3d_vec(start, end) {
+                    self.request_chunk(addr, chunk_pos);
+                }
+            }
+            Request::UnloadChunks(chunks) => {
+                for chunk_pos in &chunks {
+                    if client.loaded_chunks.remove(chunk_pos) {
+                        self.world.unwatch(*chunk_pos);
+                        let column = glam::ivec2(chunk_pos.x, chunk_pos.z);
+                        let count = client.loaded_columns.get_mut(&column).unwrap();
+                        *count -= 1;
+                        if *count == 0 {
+                            client.loaded_columns.remove(&column);
+                        }
+                    }
+                }
+
+                // Or they might not have been generated yet
+                for chunk_pos in chunks {
+                    self.stop_waiting(addr, chunk_pos);
+                }
+            }
+            Request::PlayerTransform { position, rotation } => {
+                client.moved |= rotation != client.rotation;
+                client.rotation = rotation;
+                // Still checked when the player hasn't moved so standing still counts as a move
+                let unchanged = position == client.movement.position();
+
+                let world = &self.world;
+                let is_solid = |pos| {
+                    world
+                        .get_block(pos)
+                        .is_some_and(|id| world.block_registry().is_solid(id))
+                };
+                match client.movement.check(position, self.tick, is_solid) {
+                    Ok(()) => client.moved |= !unchanged,
+                    Err(reason) => {
+                        log::warn!(
+                            "{} can't move to {} - {}",
+                            client.player_name,
+                            position,
+                            reason
+                        );
+                        client.outbox.push(Response::SetPosition {
+                            position: client.movement.position(),
+                        });
+                    }
+                }
+            }
+            Request::SetBlock { pos, id } => {
+                if let Err(reason) = self.set_block(addr, pos, id) {
+                    log::warn!(
+                        "{} can't set the block at {} - {}",
+                        self.clients[&addr].player_name,
+                        pos,
+                        reason
+                    );
+                }
+            }
+        }
+    }
+
+    /// Sends the chunk to the client if it has been generated otherwise waits for it to be
+    fn request_chunk(&mut self, addr: SocketAddr, chunk_pos: glam::IVec3) {
+        if let Some(chunk) = self.world.load_chunk(chunk_pos) {
+            self.send_chunk(addr, chunk);
+            return;
+        }
+
+        // The chunks closest to the player get generated first
+        let player_chunk_pos =
+            block_to_chunk_pos(self.clients[&addr].movement.position().floor().as_ivec3());
+        let distance = chunk_pos
+            .as_dvec3()
+            .distance_squared(player_chunk_pos.as_dvec3());
+        let waiting = self.waiting.entry(chunk_pos).or_default();
+        if waiting.is_empty() {
+            self.generator.request(chunk_pos, distance as u64);
+        } else {
+            self.generator.prioritise(chunk_pos, distance as u64);
+        }
+        waiting.insert(addr);
+    }
+
+    /// Stops the client waiting for the chunk, it doesn't get generated if no one else is
+    fn stop_waiting(&mut self, addr: SocketAddr, chunk_pos: glam::IVec3) {
+        let Some(waiting) = self.waiting.get_mut(&chunk_pos) else {
+            return;
+        };
+
+        if waiting.remove(&addr) && waiting.is_empty() {
+            self.waiting.remove(&chunk_pos);
+            self.generator.cancel(chunk_pos);
+        }
+    }
+
+    fn send_chunk(&mut self, addr: SocketAddr, chunk: Chunk) {
+        let client = self.clients.get_mut(&addr).unwrap();
+        if client.loaded_chunks.insert(chunk.pos) {
+            self.world.watch(chunk.pos);
+
+            let column = glam::ivec2(chunk.pos.x, chunk.pos.z);
+            let count = client.loaded_columns.entry(column).or_default();
+            if *count == 0 {
+                let biomes = self.world.world_gen().column_biomes(column);
+                client
+                    .outbox
+                    .push(Response::ColumnBiomes { column, biomes });
+            }
+            *count += 1;
+        }
+        client.outbox.push(Response::ChunkData(Box::new(chunk)));
+    }
+
+    /// Adds the chunks that have finished generating to the world until the deadline has
+    /// passed, at least one gets added each tick so they never pile up forever
+    fn add_generated_chunks(&mut self, deadline: Instant) {
+        while let Some(generated) = self.generator.try_recv() {
+            let added = self.world.add_generated_chunk(generated);
+            for (pos, id) in added.changed_blocks {
+                self.send_to_clients(block_to_chunk_pos(pos), Response::BlockUpdate { pos, id });
+            }
+            self.send_light_changes(added.light_changes);
+
+            let chunk = added.chunk;
+            for addr in self.waiting.remove(&chunk.pos).unwrap_or_default() {
+                self.send_chunk(addr, chunk.clone());
+            }
+
+            if Instant::now() >= deadline {
+                break;
+            }
+        }
+    }
+
+    /// Spawns, moves and despawns the entities of the other players on each client, players
+    /// can only be seen by clients with the chunk they're in loaded
+    fn update_entities(&mut self) {
+        let players = self
+            .clients
+            .values()
+            .map(|client| {
+                (
+                    client.entity_id,
+                    client.player_name.clone(),
+                    client.movement.position(),
+                    client.rotation,
+                    client.moved,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        for (entity_id, name, position, rotation, moved) in players {
+            let chunk_pos = block_to_chunk_pos(position.floor().as_ivec3());
+            for client in self.clients.values_mut() {
+                if client.entity_id == entity_id {
+                    continue;
+                }
+
+                let visible = client.loaded_chunks.contains(&chunk_pos);
+                let spawned = client.spawned_entities.contains(&entity_id);
+                let response = match (visible, spawned) {
+                    (true, false) => Response::SpawnEntity {
+                        id: entity_id,
+                        name: name.clone(),
+                        position,
+                        rotation,
+                    },
+                    (true, true) if moved => Response::MoveEntity {
+                        id: entity_id,
+                        position,
+                        rotation,
+                    },
+                    (false, true) => Response::DespawnEntity(entity_id),
+                    _ => continue,
+                };
+
+                if visible {
+                    client.spawned_entities.insert(entity_id);
+                } else {
+                    client.spawned_entities.remove(&entity_id);
+                }
+                client.outbox.push(response);
+            }
+        }
+
+        for client in self.clients.values_mut() {
+            client.moved = false;
+        }
+    }
+
+    fn schedule_update(&mut self, pos: glam::IVec3, delay: u64) {
+        self.scheduled_updates
+            .entry(self.tick + delay)
+            .or_default()
+            .insert(pos);
+    }
+
+    /// Updates the blocks scheduled for this tick or ticks that got skipped
+    fn run_block_updates(&mut self) {
+        while let Some(entry) = self.scheduled_updates.first_entry() {
+            if *entry.key() > self.tick {
+                break;
+            }
+
+            for pos in entry.remove() {
+                self.update_block(pos);
+            }
+        }
+    }
+
+    /// Makes a block that falls drop down by one if there's nothing under it
+    fn update_block(&mut self, pos: glam::IVec3) {
+        let below = pos - glam::IVec3::Y;
+        let area = self.world.load_area(block_to_chunk_pos(pos));
+        let Some(id) = get_block(&area, pos) else {
+            return;
+        };
+
+        // Blocks don't fall into chunks that haven't been generated
+        if !self.world.block_registry().falls(id) || get_block(&area, below) != Some(AIR) {
+            return;
+        }
+
+        self.change_block(area, pos, AIR);
+        let area = self.world.load_area(block_to_chunk_pos(below));
+        self.change_block(area, below, id);
+    }
+
+    /// Changes a block for a client after checking that it's allowed to
+    fn set_block(&mut self, addr: SocketAddr, pos: glam::IVec3, id: BlockID) -> Result<(), String> {
+        if !self
+            .world
+            .block_registry()
+            .iter()
+            .any(|block| block.id == id)
+        {
+            return Err(format!("block id {} doesn't exist", id));
+        }
+
+        let client = &self.clients[&addr];
+        if client.movement.position().distance(pos.as_vec3() + 0.5) > MAX_REACH {
+            return Err("the block is out of reach".to_owned());
+        }
+
+        if !client.loaded_chunks.contains(&block_to_chunk_pos(pos)) {
+            return Err("the chunk isn't loaded".to_owned());
+        }
+
+        let area = self.world.load_area(block_to_chunk_pos(pos));
+        let Some(old_id) = get_block(&area, pos) else {
+            return Err("the chunk hasn't been generated".to_owned());
+        };
+
+        if old_id == id {
+            return Ok(());
+        } else if id != AIR && old_id != AIR {
+            return Err("there's already a block there".to_owned());
+        }
+
+        self.change_block(area, pos, id);
+        Ok(())
+    }
+
+    /// Changes a block in the area around its chunk then sends the change to every client with
+    /// the chunk loaded
+    fn change_block(
+        &mut self,
+        mut area: HashMap<glam::IVec3, Chunk>,
+        pos: glam::IVec3,
+        id: BlockID,
+    ) {
+        let chunk_pos = block_to_chunk_pos(pos);
+        area.get_mut(&chunk_pos)
+            .unwrap()
+            .set_block(block_to_local_pos(pos), id);
+        let mut light_changes = update_light(&mut area, self.world.block_registry(), [pos]);
+        self.world.spread_light_down(&mut area, &mut light_changes);
+
+        let mut changed_chunks = light_changes.keys().copied().collect::<HashSet<_>>();
+        changed_chunks.insert(chunk_pos);
+        for chunk_pos in changed_chunks {
+            self.world.store_chunk(area.remove(&chunk_pos).unwrap());
+        }
+
+        self.send_to_clients(chunk_pos, Response::BlockUpdate { pos, id });
+        self.send_light_changes(light_changes);
+
+        // The block or the one on top of it might fall now
+        self.schedule_update(pos, FALL_DELAY);
+        self.schedule_update(pos + glam::IVec3::Y, FALL_DELAY);
+    }
+
+    /// Queues the response for every client with the chunk loaded
+    fn send_to_clients(&mut self, chunk_pos: glam::IVec3, response: Response) {
+        for client in self.clients.values_mut() {
+            if client.loaded_chunks.contains(&chunk_pos) {
+                client.outbox.push(response.clone());
+            }
+        }
+    }
+
+    /// Sends the new light of each chunk to the clients that have it loaded
+    fn send_light_changes(&mut self, light_changes: LightChanges) {
+        for (chunk_pos, changes) in light_changes {
+            let lights = changes.into_iter().collect();
+            self.send_to_clients(chunk_pos, Response::LightUpdate(lights));
+        }
+    }
+}
+
+/// Gets the block if its chunk is in the area
+fn get_block(area: &HashMap<glam::IVec3, Chunk>, pos: glam::IVec3) -> Option<BlockID> {
+    area.get(&block_to_chunk_pos(pos))
+        .map(|chunk| chunk.get_block(block_to_local_pos(pos)))
+}
+
+/// What to do after a tick has finished
+#[derive(Debug, PartialEq, Eq)]
+enum TickWait {
+    Sleep(Duration),
+    /// The tick took too long, the ticks that should've happened by now get skipped
+    Overrun {
+        late: Duration,
+        skipped: u32,
+    },
+}
+
+/// Keeps the ticks starting at a fixed rate
+struct TickClock {
+    tick_duration: Duration,
+    next_tick: Instant,
+}
+
+impl TickClock {
+    fn new(tick_duration: Duration, start: Instant) -> Self {
+        Self {
+            tick_duration,
+            next_tick: start,
+        }
+    }
+
+    fn advance(&mut self, now: Instant) -> TickWait {
+        self.next_tick += self.tick_duration;
+        if now <= self.next_tick {
+            return TickWait::Sleep(self.next_tick - now);
+        }
+
+        let late = now - self.next_tick;
+        self.next_tick = now;
+        TickWait::Overrun {
+            late,
+            skipped: (late.as_nanos() / self.tick_duration.as_nanos()) as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opencuboids_common::CHUNK_SIZE;
+
+    use super::*;
+    use crate::world::test_world;
+
+    #[test]
+    fn tick_clock() {
+        let start = Instant::now();
+        let tick_duration = Duration::from_millis(50);
+        let mut clock = TickClock::new(tick_duration, start);
+
+        let now = start + Duration::from_millis(20);
+        assert_eq!(
+            clock.advance(now),
+            TickWait::Sleep(Duration::from_millis(30))
+        );
+
+        // The next tick starts right after the one that ran over
+        let now = start + Duration::from_millis(230);
+        assert_eq!(
+            clock.advance(now),
+            TickWait::Overrun {
+                late: Duration::from_millis(130),
+                skipped: 2
+            }
+        );
+        assert_eq!(
+            clock.advance(now + Duration::from_millis(10)),
+            TickWait::Sleep(Duration::from_millis(40))
+        );
+    }
+
+    #[test]
+    fn chunks_generated_once() {
+        let (events, receiver) = mpsc::channel();
+        let spawn_position = glam::vec3(0.5, 270.0, 0.5);
+        let world = test_world("generated-once");
+        let mut simulation = Simulation::new(Arc::new(world), spawn_position, receiver);
+
+        let chunk_pos = glam::ivec3(0, 8, 0);
+        let clients = ["127.0.0.1:1", "127.0.0.1:2"].map(|addr| {
+            let addr = addr.parse().unwrap();
+            let (sender, responses) = mpsc::channel();
+            let player_name = "tester".to_owned();
+            events
+                .send(ClientEvent::Joined {
+                    addr,
+                    player_name,
+                    sender,
+                })
+                .unwrap();
+            (addr, responses)
+        });
+
+        // The second client asks once a worker is already generating it for the first
+        let request = || Request::ChunkRange {
+            start: chunk_pos,
+            end: chunk_pos + 1,
+        };
+        let addr = clients[0].0;
+        events
+            .send(ClientEvent::Request {
+                addr,
+                request: request(),
+            })
+            .unwrap();
+        simulation.tick(Duration::ZERO);
+        let start = Instant::now();
+        while simulation.generator.taken() == 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let addr = clients[1].0;
+        events
+            .send(ClientEvent::Request {
+                addr,
+                request: request(),
+            })
+            .unwrap();
+
+        let mut received = [0; 2];
+        let start = Instant::now();
+        while received != [1, 1] {
+            simulation.tick(Duration::ZERO);
+            for (count, (_, responses)) in received.iter_mut().zip(&clients) {
+                *count += responses
+                    .try_iter()
+                    .filter(|response| {
+                        matches!(response, Response::ChunkData(chunk) if chunk.pos == chunk_pos)
+                    })
+                    .count();
+            }
+            assert!(received.iter().all(|count| *count <= 1));
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(simulation.generator.taken(), 1);
+        assert!(simulation.waiting.is_empty());
+    }
+
+    #[test]
+    fn biomes_sent_per_column() {
+        let (events, receiver) = mpsc::channel();
+        let spawn_position = glam::vec3(0.5, 270.0, 0.5);
+        let world = test_world("column-biomes");
+        let mut simulation = Simulation::new(Arc::new(world), spawn_position, receiver);
+
+        let addr = "127.0.0.1:1".parse().unwrap();
+        let (sender, responses) = mpsc::channel();
+        let player_name = "tester".to_owned();
+        events
+            .send(ClientEvent::Joined {
+                addr,
+                player_name,
+                sender,
+            })
+            .unwrap();
+
+        // Two chunks on top of each other in the same column
+        let column = glam::ivec2(-1, 2);
+        let start = glam::ivec3(column.x, 8, column.y);
+        let request = Request::ChunkRange {
+            start,
+            end: start + glam::ivec3(1, 2, 1),
+        };
+        events.send(ClientEvent::Request { addr, request }).unwrap();
+
+        let mut received = Vec::new();
+        let start = Instant::now();
+        while received
+            .iter()
+            .filter(|response| matches!(response, Response::ChunkData(_)))
+            .count()
+            < 2
+        {
+            simulation.tick(Duration::ZERO);
+            received.extend(responses.try_iter());
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        // The biomes come once, before the first chunk
+        let Response::ColumnBiomes {
+            column: sent_column,
+            biomes,
+        } = &received[0]
+        else {
+            panic!("expected the biomes first, got {:?}", received[0]);
+        };
+        assert_eq!(*sent_column, column);
+        assert_eq!(
+            received
+                .iter()
+                .filter(|response| matches!(response, Response::ColumnBiomes { .. }))
+                .count(),
+            1
+        );
+
+        let world_gen = simulation.world.world_gen();
+        assert_eq!(biomes.len(), CHUNK_SIZE * CHUNK_SIZE);
+        for (x, z) in [(0, 0), (31, 0), (5, 17), (31, 31)] {
+            assert_eq!(
+                biomes[x + z * CHUNK_SIZE],
+                world_gen.biome_at(-32 + x as i32, 64 + z as i32)
+            );
+        }
+    }
+
+    #[test]
+    fn skipped_ticks_counted() {
+        let (events, receiver) = mpsc::channel();
+        let spawn_position = glam::vec3(0.5, 270.0, 0.5);
+        let world = test_world("skipped-ticks");
+        let mut simulation = Simulation::new(Arc::new(world), spawn_position, receiver);
+
+        let addr = "127.0.0.1:1".parse().unwrap();
+        let (sender, responses) = mpsc::channel();
+        let player_name = "tester".to_owned();
+        events
+            .send(ClientEvent::Joined {
+                addr,
+                player_name,
+                sender,
+            })
+            .unwrap();
+        simulation.tick(Duration::ZERO);
+        simulation.schedule_update(spawn_position.as_ivec3(), 2);
+
+        // The server fell behind, the player kept moving at the speed limit the whole time
+        simulation.skip_ticks(3);
+        let limits = MovementLimits::default();
+        let distance = limits.max_speed * 4.0 / simulation.tick_rate as f32 + limits.tolerance;
+        let position = spawn_position + glam::vec3(distance, 0.0, 0.0);
+        let request = Request::PlayerTransform {
+            position,
+            rotation: glam::Vec2::ZERO,
+        };
+        events.send(ClientEvent::Request { addr, request }).unwrap();
+        simulation.tick(Duration::ZERO);
+
+        assert_eq!(simulation.clients[&addr].movement.position(), position);
+        assert!(!responses
+            .try_iter()
+            .any(|response| matches!(response, Response::SetPosition { .. })));
+        // The skipped tick still updates its blocks
+        assert!(simulation.scheduled_updates.is_empty());
+    }
+
+    #[test]
+    fn blocks_fall() {
+        let world = test_world("blocks-fall");
+        let sand = world.block_registry().id("sand").unwrap();
+
+        let (events, receiver) = mpsc::channel();
+        let spawn_position = glam::vec3(0.5, 270.0, 0.5);
+        let mut simulation = Simulation::new(Arc::new(world), spawn_position, receiver);
+
+        // Way up in the sky with nothing generated below
+        let addr = "127.0.0.1:1".parse().unwrap();
+        let (sender, responses) = mpsc::channel();
+        let chunk_pos = glam::ivec3(0, 8, 0);
+        let player_name = "tester".to_owned();
+        events
+            .send(ClientEvent::Joined {
+                addr,
+                player_name,
+                sender,
+            })
+            .unwrap();
+        let request = Request::ChunkRange {
+            start: chunk_pos,
+            end: chunk_pos + 1,
+        };
+        events.send(ClientEvent::Request { addr, request }).unwrap();
+
+        // Wait for the workers to generate it
+        let start = Instant::now();
+        loop {
+            simulation.tick(Duration::ZERO);
+            if responses
+                .try_iter()
+                .any(|response| matches!(response, Response::ChunkData(_)))
+            {
+                break;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let request = Request::SetBlock {
+            pos: glam::ivec3(0, 264, 0),
+            id: sand,
+        };
+        events.send(ClientEvent::Request { addr, request }).unwrap();
+
+        for _ in 0..8 * FALL_DELAY + 2 {
+            simulation.tick(Duration::ZERO);
+        }
+
+        // The sand lands at the bottom of the chunk since the one under it isn't generated
+        let sand_updates = responses.try_iter().filter_map(|response| match response {
+            Response::BlockUpdate { pos, id } if id == sand => Some(pos),
+            _ => None,
+        });
+        assert!(sand_updates.eq((256..=264).rev().map(|y| glam::ivec3(0, y, 0))));
+
+        drop(events);
+        assert!(!simulation.tick(Duration::ZERO));
+    }
+}
@@ -0,0 +1,385 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use opencuboids_common::{
+    block_to_chunk_pos, block_to_local_pos, iter_3d, light_chunk, update_light, BlockID,
+    BlockRegistry, Chunk, LightChanges,
+};
+
+use crate::{generator::Generated, world_gen::WorldGen, WorldSave};
+
+/// How long a chunk stays in memory after the last player watching it has unloaded it
+pub const UNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A generated chunk that has been added to the world and what adding it changed around it
+pub struct AddedChunk {
+    pub chunk: Chunk,
+    /// Blocks of its structures that were placed into the chunks around it
+    pub changed_blocks: Vec<(glam::IVec3, BlockID)>,
+    pub light_changes: LightChanges,
+}
+
+struct LoadedChunk {
+    chunk: Chunk,
+    /// How many players have the chunk loaded
+    watchers: usize,
+    /// When the chunk last had no watchers, only used when it has none
+    unwatched_since: Instant,
+}
+
+/// The chunks in memory shared between every player, each chunk gets loaded or generated once
+/// then stays until no one has watched it for the unload timeout
+///
+/// Changed chunks get written through to the world save so flushing it saves everything.
+pub struct World {
+    chunks: RwLock<HashMap<glam::IVec3, LoadedChunk>>,
+    world_save: Arc<Mutex<WorldSave>>,
+    world_gen: WorldGen,
+    block_registry: Arc<BlockRegistry>,
+}
+
+impl World {
+    pub fn new(
+        world_save: Arc<Mutex<WorldSave>>,
+        world_gen: WorldGen,
+        block_registry: Arc<BlockRegistry>,
+    ) -> Self {
+        Self {
+            chunks: RwLock::default(),
+            world_save,
+            world_gen,
+            block_registry,
+        }
+    }
+
+    pub fn world_save(&self) -> Arc<Mutex<WorldSave>> {
+        self.world_save.clone()
+    }
+
+    pub fn block_registry(&self) -> &BlockRegistry {
+        &self.block_registry
+    }
+
+    pub fn world_gen(&self) -> &WorldGen {
+        &self.world_gen
+    }
+
+    /// Gets a copy of the chunk if it's in memory
+    #[cfg(test)]
+    pub fn get_chunk(&self, chunk_pos: glam::IVec3) -> Option<Chunk> {
+        let chunks = self.chunks.read().unwrap();
+        chunks.get(&chunk_pos).map(|loaded| loaded.chunk.clone())
+    }
+
+    /// Gets the block if its chunk is in memory
+    pub fn get_block(&self, pos: glam::IVec3) -> Option<BlockID> {
+        let chunks = self.chunks.read().unwrap();
+        chunks
+            .get(&block_to_chunk_pos(pos))
+            .map(|loaded| loaded.chunk.get_block(block_to_local_pos(pos)))
+    }
+
+    #[cfg(test)]
+    pub fn watchers(&self, chunk_pos: glam::IVec3) -> usize {
+        self.chunks
+            .read()
+            .unwrap()
+            .get(&chunk_pos)
+            .map_or(0, |loaded| loaded.watchers)
+    }
+
+    /// Keeps the chunk loaded until the player stops watching it, the chunk has to be loaded
+    pub fn watch(&self, chunk_pos: glam::IVec3) {
+        let mut chunks = self.chunks.write().unwrap();
+        chunks.get_mut(&chunk_pos).unwrap().watchers += 1;
+    }
+
+    pub fn unwatch(&self, chunk_pos: glam::IVec3) {
+        let mut chunks = self.chunks.write().unwrap();
+        let loaded = chunks.get_mut(&chunk_pos).unwrap();
+        loaded.watchers -= 1;
+        if loaded.watchers == 0 {
+            loaded.unwatched_since = Instant::now();
+        }
+    }
+
+    /// Removes the chunks that haven't been watched for the unload timeout, returns how many
+    /// were removed
+    pub fn unload_unwatched(&self, now: Instant) -> usize {
+        let mut chunks = self.chunks.write().unwrap();
+        let count = chunks.len();
+        chunks.retain(|_, loaded| {
+            loaded.watchers > 0 || now.duration_since(loaded.unwatched_since) < UNLOAD_TIMEOUT
+        });
+        count - chunks.len()
+    }
+
+    /// Loads the chunk from the save if it isn't in memory yet, returns none if it hasn't been
+    /// generated
+    pub fn load_chunk(&self, chunk_pos: glam::IVec3) -> Option<Chunk> {
+        if let Some(loaded) = self.chunks.read().unwrap().get(&chunk_pos) {
+            return Some(loaded.chunk.clone());
+        }
+
+        let chunk = match self.world_save.lock().unwrap().load_chunk(chunk_pos) {
+            Ok(chunk) => chunk?,
+            Err(err) => {
+                log::error!("Failed to load chunk at {} - {}", chunk_pos, err);
+                return None;
+            }
+        };
+
+        self.insert(chunk.clone());
+        Some(chunk)
+    }
+
+    /// Puts the chunk in memory keeping its watchers
+    fn insert(&self, chunk: Chunk) {
+        let mut chunks = self.chunks.write().unwrap();
+        match chunks.get_mut(&chunk.pos) {
+            Some(loaded) => loaded.chunk = chunk,
+            None => {
+                let loaded = LoadedChunk {
+                    chunk,
+                    watchers: 0,
+                    unwatched_since: Instant::now(),
+                };
+                chunks.insert(loaded.chunk.pos, loaded);
+            }
+        }
+    }
+
+    /// Replaces the chunk in memory and in the save
+    pub fn store_chunk(&self, chunk: Chunk) {
+        self.world_save.lock().unwrap().save_chunk(chunk.clone());
+        self.insert(chunk);
+    }
+
+    /// Loads the chunk and every chunk around it that has been generated, so light can spread
+    /// between them
+    ///
+    /// Light can't spread further than one chunk anyway, except for sky light going straight
+    /// down which gets carried further with spread_light_down.
+    pub fn load_area(&self, chunk_pos: glam::IVec3) -> HashMap<glam::IVec3, Chunk> {
+        iter_3d(-1, 2)
+            .filter_map(|offset| self.load_chunk(chunk_pos + offset))
+            .map(|chunk| (chunk.pos, chunk))
+            .collect()
+    }
+
+    /// Carries light changes that got to the bottom of the area on into the chunks under it,
+    /// loading them and the chunks around them into the area until the changes stop or there
+    /// are no more generated chunks
+    ///
+    /// Only sky light going straight down can get that far, it keeps going until it hits an
+    /// opaque block.
+    pub fn spread_light_down(
+        &self,
+        area: &mut HashMap<glam::IVec3, Chunk>,
+        light_changes: &mut LightChanges,
+    ) {
+        let mut changed_chunks = light_changes.keys().copied().collect::<Vec<_>>();
+        while let Some(chunk_pos) = changed_chunks.pop() {
+            let below_chunk_pos = chunk_pos - glam::IVec3::Y;
+            if area.contains_key(&below_chunk_pos) {
+                continue;
+            }
+
+            let below = light_changes[&chunk_pos]
+                .keys()
+                .filter(|pos| block_to_local_pos(**pos).y == 0)
+                .map(|pos| *pos - glam::IVec3::Y)
+                .collect::<Vec<_>>();
+            if below.is_empty() || self.load_chunk(below_chunk_pos).is_none() {
+                continue;
+            }
+
+            for (chunk_pos, chunk) in self.load_area(below_chunk_pos) {
+                area.entry(chunk_pos).or_insert(chunk);
+            }
+            for (chunk_pos, changes) in update_light(area, &self.block_registry, below) {
+                light_changes.entry(chunk_pos).or_default().extend(changes);
+                changed_chunks.push(chunk_pos);
+            }
+        }
+    }
+
+    /// Adds a chunk from the world gen, lighting it and placing its structures, along with how
+    /// it changed the chunks around it
+    ///
+    /// The changed chunks have already been stored. If the chunk was already added the one in
+    /// the world gets kept.
+    pub fn add_generated_chunk(&self, generated: Generated) -> AddedChunk {
+        let (mut chunk, outside_blocks) = generated;
+        let chunk_pos = chunk.pos;
+        if let Some(chunk) = self.load_chunk(chunk_pos) {
+            return AddedChunk {
+                chunk,
+                changed_blocks: Vec::new(),
+                light_changes: LightChanges::new(),
+            };
+        }
+
+        let world_gen = &self.world_gen;
+        // Parts of structures from chunks that were generated before this one
+        let pending_blocks = self
+            .world_save
+            .lock()
+            .unwrap()
+            .take_pending_blocks(chunk_pos);
+        for (pos, id) in pending_blocks {
+            world_gen.place_structure_block(&mut chunk, pos, id);
+        }
+
+        // Light the chunk along with the chunks around it that have already been generated
+        let mut area = self.load_area(chunk_pos);
+        area.insert(chunk_pos, chunk);
+        let mut light_changes = light_chunk(&mut area, &self.block_registry, chunk_pos);
+
+        // Parts of structures going into the neighbours, the ones that haven't been generated
+        // yet get them once they are
+        let mut changed_blocks = Vec::new();
+        let mut pending_blocks = Vec::new();
+        for (pos, id) in outside_blocks {
+            match area.get_mut(&block_to_chunk_pos(pos)) {
+                Some(neighbour) => {
+                    if world_gen.place_structure_block_in_generated(neighbour, pos, id) {
+                        changed_blocks.push((pos, id));
+                    }
+                }
+                None => pending_blocks.push((pos, id)),
+            }
+        }
+        self.world_save
+            .lock()
+            .unwrap()
+            .add_pending_blocks(pending_blocks);
+        let changed_positions = changed_blocks.iter().map(|(pos, _)| *pos);
+        for (chunk_pos, changes) in update_light(&mut area, &self.block_registry, changed_positions)
+        {
+            light_changes.entry(chunk_pos).or_default().extend(changes);
+        }
+        self.spread_light_down(&mut area, &mut light_changes);
+
+        // The light of the new chunk gets sent along with it
+        let chunk = area.remove(&chunk_pos).unwrap();
+        light_changes.remove(&chunk_pos);
+        self.store_chunk(chunk.clone());
+        let changed_chunks = light_changes
+            .keys()
+            .copied()
+            .chain(
+                changed_blocks
+                    .iter()
+                    .map(|(pos, _)| block_to_chunk_pos(*pos)),
+            )
+            .collect::<HashSet<_>>();
+        for chunk_pos in changed_chunks {
+            self.store_chunk(area.remove(&chunk_pos).unwrap());
+        }
+
+        AddedChunk {
+            chunk,
+            changed_blocks,
+            light_changes,
+        }
+    }
+}
+
+/// Opens a new world with the default blocks in its own directory for a test
+#[cfg(test)]
+pub fn test_world(name: &str) -> World {
+    let registry = BlockRegistry::default();
+    let world_save = WorldSave::open(crate::world_save::temp_world_dir(name)).unwrap();
+    let world_gen = WorldGen::new(world_save.seed(), &registry).unwrap();
+    World::new(
+        Arc::new(Mutex::new(world_save)),
+        world_gen,
+        Arc::new(registry),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use opencuboids_common::{Light, AIR};
+
+    use super::*;
+
+    #[test]
+    fn unload_after_timeout() {
+        let world = test_world("world-unload");
+
+        let chunk_pos = glam::ivec3(0, 0, 0);
+        let generated = world.world_gen().gen_chunk(chunk_pos);
+        let chunk = world.add_generated_chunk(generated).chunk;
+        world.watch(chunk_pos);
+        world.watch(chunk_pos);
+        assert_eq!(world.watchers(chunk_pos), 2);
+
+        // Watched chunks stay however long it's been
+        let later = Instant::now() + UNLOAD_TIMEOUT;
+        assert_eq!(world.unload_unwatched(later), 0);
+
+        world.unwatch(chunk_pos);
+        world.unwatch(chunk_pos);
+        assert_eq!(world.unload_unwatched(Instant::now()), 0);
+        assert!(world.get_chunk(chunk_pos).is_some());
+
+        let later = Instant::now() + UNLOAD_TIMEOUT;
+        assert_eq!(world.unload_unwatched(later), 1);
+        assert!(world.get_chunk(chunk_pos).is_none());
+
+        // Comes back from the save
+        let reloaded = world.load_chunk(chunk_pos).unwrap();
+        assert_eq!(
+            bincode::serialize(&reloaded).unwrap(),
+            bincode::serialize(&chunk).unwrap()
+        );
+    }
+
+    #[test]
+    fn sky_light_goes_below_area() {
+        let world = test_world("world-sky-light");
+        let stone = world.block_registry().id("stone").unwrap();
+
+        // A column of empty chunks lit as if they were under open sky
+        for y in 0..3 {
+            world.add_generated_chunk((Chunk::new(glam::ivec3(0, y, 0)), Vec::new()));
+        }
+        let get_light = |pos: glam::IVec3| {
+            world
+                .load_chunk(block_to_chunk_pos(pos))
+                .unwrap()
+                .get_light(block_to_local_pos(pos))
+        };
+        let bottom = glam::ivec3(5, 3, 5);
+        assert_eq!(get_light(bottom), Light::SKY);
+
+        // A chunk with a stone floor on top shades the whole column, further down than the
+        // chunks around it
+        let mut roof = Chunk::new(glam::ivec3(0, 3, 0));
+        for pos in iter_3d(0, 32).filter(|pos| pos.y == 0) {
+            roof.set_block(pos.as_uvec3(), stone);
+        }
+        let added = world.add_generated_chunk((roof, Vec::new()));
+        assert!(added.light_changes[&glam::IVec3::ZERO].contains_key(&bottom));
+        assert_eq!(get_light(bottom), Light::default());
+
+        // Making a hole in it lets the sky back in all the way down
+        let hole = glam::ivec3(5, 96, 5);
+        let mut area = world.load_area(block_to_chunk_pos(hole));
+        area.get_mut(&block_to_chunk_pos(hole))
+            .unwrap()
+            .set_block(block_to_local_pos(hole), AIR);
+        let mut light_changes = update_light(&mut area, world.block_registry(), [hole]);
+        world.spread_light_down(&mut area, &mut light_changes);
+        assert_eq!(light_changes[&glam::IVec3::ZERO][&bottom], Light::SKY);
+        assert_eq!(
+            area[&glam::IVec3::ZERO].get_light(block_to_local_pos(bottom)),
+            Light::SKY
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+use opencuboids_common::Biome;
+
+use super::structure::{Template, BOULDER, OAK_TREE, SPRUCE_TREE};
+
+/// Tallest a decoration can be
+pub const MAX_DECORATION_HEIGHT: i32 = 3;
+
+/// A column of blocks that sometimes gets placed on top of the surface
+pub struct Decoration {
+    pub block: &'static str,
+    /// Chance of each surface block getting one
+    pub chance: f64,
+    pub max_height: i32,
+}
+
+/// A structure that sometimes gets placed on top of the surface
+pub struct StructureRule {
+    pub template: &'static Template,
+    /// Chance of each surface block getting one
+    pub chance: f64,
+}
+
+/// How the terrain of a biome gets generated
+pub struct BiomeParams {
+    /// Height the surface is around
+    pub base_height: f64,
+    /// How far the hills and valleys go above and below the base height
+    pub height_variation: f64,
+    /// The top block of the surface
+    pub surface: &'static str,
+    /// The blocks under the surface before the stone starts
+    pub subsurface: &'static str,
+    pub decoration: Option<Decoration>,
+    /// Only one structure goes on a surface block, earlier ones get tried first
+    pub structures: &'static [StructureRule],
+}
+
+impl BiomeParams {
+    pub fn get(biome: Biome) -> &'static Self {
+        &BIOMES[biome as usize]
+    }
+}
+
+/// Uses the same order as Biome::ALL
+const BIOMES: [BiomeParams; 5] = [
+    // Plains
+    BiomeParams {
+        base_height: 16.0,
+        height_variation: 12.0,
+        surface: "grass",
+        subsurface: "dirt",
+        decoration: None,
+        structures: &[
+            StructureRule {
+                template: &OAK_TREE,
+                chance: 0.002,
+            },
+            StructureRule {
+                template: &BOULDER,
+                chance: 0.0005,
+            },
+        ],
+    },
+    // Forest
+    BiomeParams {
+        base_height: 20.0,
+        height_variation: 24.0,
+        surface: "grass",
+        subsurface: "dirt",
+        decoration: None,
+        structures: &[StructureRule {
+            template: &OAK_TREE,
+            chance: 0.03,
+        }],
+    },
+    // Desert
+    BiomeParams {
+        base_height: 12.0,
+        height_variation: 8.0,
+        surface: "sand",
+        subsurface: "sand",
+        decoration: Some(Decoration {
+            block: "cactus",
+            chance: 0.004,
+            max_height: MAX_DECORATION_HEIGHT,
+        }),
+        structures: &[],
+    },
+    // Tundra
+    BiomeParams {
+        base_height: 18.0,
+        height_variation: 16.0,
+        surface: "snow",
+        subsurface: "dirt",
+        decoration: None,
+        structures: &[StructureRule {
+            template: &SPRUCE_TREE,
+            chance: 0.01,
+        }],
+    },
+    // Mountains
+    BiomeParams {
+        base_height: 40.0,
+        height_variation: 64.0,
+        surface: "stone",
+        subsurface: "stone",
+        decoration: None,
+        structures: &[
+            StructureRule {
+                template: &BOULDER,
+                chance: 0.003,
+            },
+            StructureRule {
+                template: &SPRUCE_TREE,
+                chance: 0.002,
+            },
+        ],
+    },
+];
+
+/// Decides the biomes from noise for the temperature and humidity
+pub struct Climate {
+    temperature: Fbm<Perlin>,
+    humidity: Fbm<Perlin>,
+}
+
+impl Climate {
+    pub fn new(temperature_seed: u32, humidity_seed: u32) -> Self {
+        let noise = |seed| {
+            Fbm::new(seed)
+                .set_octaves(2)
+                .set_frequency(1.0 / 1024.0)
+                .set_lacunarity(2.0)
+        };
+        Self {
+            temperature: noise(temperature_seed),
+            humidity: noise(humidity_seed),
+        }
+    }
+
+    /// Gets how much each biome in the order of Biome::ALL applies to a column, the weights add
+    /// up to 1 and change smoothly between biomes so they can be blended together
+    pub fn weights(&self, x: i32, z: i32) -> [f64; 5] {
+        let point = [x as f64, z as f64];
+        let temperature = self.temperature.get(point);
+        let humidity = self.humidity.get(point);
+
+        let cold = 1.0 - smoothstep(-0.4, -0.1, temperature);
+        let hot = smoothstep(0.1, 0.4, temperature);
+        let mild = 1.0 - cold - hot;
+        let wet = smoothstep(-0.15, 0.15, humidity);
+        let mild_wet = smoothstep(0.0, 0.3, humidity);
+
+        [
+            mild * (1.0 - mild_wet),
+            mild * mild_wet + hot * wet,
+            hot * (1.0 - wet),
+            cold * wet,
+            cold * (1.0 - wet),
+        ]
+    }
+}
+
+/// Goes from 0 at the start to 1 at the end with a smooth curve
+fn smoothstep(start: f64, end: f64, x: f64) -> f64 {
+    let t = ((x - start) / (end - start)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smooth_weights() {
+        let climate = Climate::new(1, 2);
+        let mut found = [false; 5];
+        for x in (-8192..8192).step_by(16) {
+            for z in (-8192..8192).step_by(256) {
+                let weights = climate.weights(x, z);
+                assert!((weights.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+
+                // Neighbouring columns have almost the same weights
+                let next = climate.weights(x + 1, z);
+                for (weight, next) in weights.iter().zip(next) {
+                    assert!(*weight >= 0.0 && (weight - next).abs() < 0.05);
+                }
+
+                for (found, weight) in found.iter_mut().zip(weights) {
+                    *found |= weight == 1.0;
+                }
+            }
+        }
+
+        // Every biome shows up somewhere on its own
+        assert_eq!(found, [true; 5]);
+    }
+}
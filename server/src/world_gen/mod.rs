@@ -0,0 +1,590 @@
+mod biome;
+mod structure;
+
+use std::collections::HashMap;
+
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+use opencuboids_common::{
+    block_to_chunk_pos, block_to_local_pos, Biome, BlockID, BlockRegistry, Chunk, AIR, CHUNK_SIZE,
+};
+
+use self::{
+    biome::{BiomeParams, Climate, MAX_DECORATION_HEIGHT},
+    structure::Structure,
+};
+
+/// How far the 3D noise can move the surface up or down, this makes the cliffs and overhangs
+const OVERHANG: f64 = 8.0;
+const DIRT_DEPTH: i32 = 3;
+/// Caves are where two noise values are both close to zero, which makes long winding tunnels
+const CAVE_RADIUS: f64 = 0.06;
+
+/// The blocks of a biome
+struct BiomeBlocks {
+    surface: BlockID,
+    subsurface: BlockID,
+    decoration: Option<BlockID>,
+    /// Structures with the chance of them being on a surface block
+    structures: Vec<(Structure, f64)>,
+}
+
+/// The ids of the blocks used by world generation
+struct GenBlocks {
+    stone: BlockID,
+    /// Uses the same order as Biome::ALL
+    biomes: Vec<BiomeBlocks>,
+}
+
+impl GenBlocks {
+    fn new(registry: &BlockRegistry) -> std::io::Result<Self> {
+        let get = |name| {
+            registry.id(name).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("world generation requires a block named {}", name),
+                )
+            })
+        };
+
+        let biomes = Biome::ALL
+            .iter()
+            .map(|biome| {
+                let params = BiomeParams::get(*biome);
+                Ok(BiomeBlocks {
+                    surface: get(params.surface)?,
+                    subsurface: get(params.subsurface)?,
+                    decoration: match &params.decoration {
+                        Some(decoration) => Some(get(decoration.block)?),
+                        None => None,
+                    },
+                    structures: params
+                        .structures
+                        .iter()
+                        .map(|rule| Ok((Structure::new(rule.template, get)?, rule.chance)))
+                        .collect::<std::io::Result<_>>()?,
+                })
+            })
+            .collect::<std::io::Result<_>>()?;
+
+        Ok(Self {
+            stone: get("stone")?,
+            biomes,
+        })
+    }
+}
+
+/// Generates the blocks of the world from the seed, the same seed always generates the same
+/// world no matter which order the chunks are generated in
+pub struct WorldGen {
+    seed: u64,
+    blocks: GenBlocks,
+    /// Decides which block wins where structures overlap
+    registry: BlockRegistry,
+    climate: Climate,
+    /// Height of the surface at each column, scaled by the biomes
+    height: Fbm<Perlin>,
+    /// Moves the surface up or down in 3D
+    detail: Fbm<Perlin>,
+    caves: [Perlin; 2],
+}
+
+impl WorldGen {
+    pub fn new(seed: u64, registry: &BlockRegistry) -> std::io::Result<Self> {
+        Ok(Self {
+            seed,
+            blocks: GenBlocks::new(registry)?,
+            registry: registry.clone(),
+            climate: Climate::new(noise_seed(seed, 4), noise_seed(seed, 5)),
+            height: Fbm::new(noise_seed(seed, 0))
+                .set_octaves(5)
+                .set_frequency(1.0 / 256.0)
+                .set_lacunarity(2.0),
+            detail: Fbm::new(noise_seed(seed, 1))
+                .set_octaves(3)
+                .set_frequency(1.0 / 32.0)
+                .set_lacunarity(2.0),
+            caves: [
+                Perlin::new(noise_seed(seed, 2)),
+                Perlin::new(noise_seed(seed, 3)),
+            ],
+        })
+    }
+
+    /// Generates the terrain of a chunk along with the structures on its surface, also returns
+    /// the world positions and blocks of the parts of the structures that go into neighbouring
+    /// chunks
+    pub fn gen_chunk(&self, chunk_pos: glam::IVec3) -> (Chunk, Vec<(glam::IVec3, BlockID)>) {
+        let (mut chunk, structures) = self.gen_terrain(chunk_pos);
+
+        // Placed after all the terrain so the structures don't get overwritten by it
+        let mut outside_blocks = HashMap::<glam::IVec3, BlockID>::new();
+        for (origin, structure) in structures {
+            for (offset, id) in &structure.blocks {
+                let pos = origin + *offset;
+                if block_to_chunk_pos(pos) == chunk_pos {
+                    self.place_structure_block(&mut chunk, pos, *id);
+                } else {
+                    // Only the block that wins goes to the neighbour, it might not get another
+                    // chance to win over the others there
+                    let outside_id = outside_blocks.entry(pos).or_insert(*id);
+                    if self.structure_rank(*id) > self.structure_rank(*outside_id) {
+                        *outside_id = *id;
+                    }
+                }
+            }
+        }
+        (chunk, outside_blocks.into_iter().collect())
+    }
+
+    /// Generates the terrain of a chunk, returns the structures that go on its surface with the
+    /// world positions they start at
+    fn gen_terrain(&self, chunk_pos: glam::IVec3) -> (Chunk, Vec<(glam::IVec3, &Structure)>) {
+        let mut chunk = Chunk::new(chunk_pos);
+        let start_pos = chunk_pos * CHUNK_SIZE as i32;
+        let end_pos = start_pos + CHUNK_SIZE as i32;
+
+        let mut structures = Vec::new();
+        for x in start_pos.x..end_pos.x {
+            for z in start_pos.z..end_pos.z {
+                let structure = self.gen_column(x, z, start_pos.y, end_pos.y, |y, id| {
+                    let local_pos = glam::ivec3(x, y, z) - start_pos;
+                    chunk.set_block(local_pos.as_uvec3(), id);
+                });
+
+                if let Some((surface_y, structure)) = structure {
+                    structures.push((glam::ivec3(x, surface_y + 1, z), structure));
+                }
+            }
+        }
+        (chunk, structures)
+    }
+
+    /// Places a block of a structure at a world position in the chunk, returns whether the block
+    /// changed
+    ///
+    /// Structures only go where the terrain is air. Where they overlap, opaque blocks win over
+    /// transparent ones then higher ids over lower ones, so the result is the same no matter
+    /// which order the structures get placed in.
+    pub fn place_structure_block(&self, chunk: &mut Chunk, pos: glam::IVec3, id: BlockID) -> bool {
+        let local_pos = block_to_local_pos(pos);
+        let old_id = chunk.get_block(local_pos);
+        if self.structure_rank(id) <= self.structure_rank(old_id)
+            || (old_id != AIR && self.block_at(pos) != AIR)
+        {
+            return false;
+        }
+
+        chunk.set_block(local_pos, id);
+        true
+    }
+
+    /// Orders the blocks of overlapping structures, opaque blocks win over transparent ones then
+    /// higher ids over lower ones
+    fn structure_rank(&self, id: BlockID) -> (bool, BlockID) {
+        (!self.registry.is_transparent(id), id)
+    }
+
+    /// Places a block of a structure into a chunk that was generated before, returns whether the
+    /// block changed
+    ///
+    /// Players could have changed the chunk since, so the block only goes where the terrain was
+    /// generated as air and still is. Blocks of other structures there get kept since they can't
+    /// be told apart from blocks placed by players, so where structures overlap across the border
+    /// the result can depend on which chunk got generated first.
+    pub fn place_structure_block_in_generated(
+        &self,
+        chunk: &mut Chunk,
+        pos: glam::IVec3,
+        id: BlockID,
+    ) -> bool {
+        let local_pos = block_to_local_pos(pos);
+        if chunk.get_block(local_pos) != AIR || self.block_at(pos) != AIR {
+            return false;
+        }
+
+        chunk.set_block(local_pos, id);
+        true
+    }
+
+    /// Gets the block that gets generated at a world block position
+    pub fn block_at(&self, pos: glam::IVec3) -> BlockID {
+        let mut block = AIR;
+        self.gen_column(pos.x, pos.z, pos.y, pos.y + 1, |_, id| block = id);
+        block
+    }
+
+    /// Gets the biome that has the most say over a column, used for things like tinting that
+    /// should be the same on the client
+    pub fn biome_at(&self, x: i32, z: i32) -> Biome {
+        let weights = self.climate.weights(x, z);
+        let (index, _) = weights
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .unwrap();
+        Biome::ALL[index]
+    }
+
+    /// Gets the biome of every block column in a column of chunks, x first then z
+    pub fn column_biomes(&self, column: glam::IVec2) -> Vec<Biome> {
+        let start = column * CHUNK_SIZE as i32;
+        (0..CHUNK_SIZE as i32)
+            .flat_map(|z| (0..CHUNK_SIZE as i32).map(move |x| (x, z)))
+            .map(|(x, z)| self.biome_at(start.x + x, start.y + z))
+            .collect()
+    }
+
+    /// Gets the height of the highest block at a column, or the lowest possible surface height if
+    /// a cave goes deeper than that
+    pub fn surface_height(&self, x: i32, z: i32) -> i32 {
+        let height = self.height(x, z, &self.climate.weights(x, z));
+        let top = (height + OVERHANG).ceil() as i32 + MAX_DECORATION_HEIGHT;
+        let bottom = (height - OVERHANG).floor() as i32;
+        (bottom..top)
+            .rev()
+            .find(|y| self.block_at(glam::ivec3(x, *y, z)) != AIR)
+            .unwrap_or(bottom)
+    }
+
+    /// Calls set with the y and block of every solid block in a column from the start y to the
+    /// end y, returns the structure that goes on the column if there is one along with the y of
+    /// the highest surface block in the range it goes on top of
+    fn gen_column(
+        &self,
+        x: i32,
+        z: i32,
+        start_y: i32,
+        end_y: i32,
+        mut set: impl FnMut(i32, BlockID),
+    ) -> Option<(i32, &Structure)> {
+        let weights = self.climate.weights(x, z);
+        let height = self.height(x, z, &weights);
+        let biome = self.surface_biome(x, z, &weights);
+        let blocks = &self.blocks.biomes[biome as usize];
+        let decoration = self.decoration(x, z, biome);
+        let structure = match decoration {
+            Some(_) => None,
+            None => self.structure(x, z, biome),
+        };
+        let mut surface_y = None;
+
+        // How many solid blocks and how much air are directly above, the blocks around the range
+        // are checked to know where the surface is, which makes it the same across chunk borders
+        let mut depth = i32::MAX;
+        let mut air = 0;
+        let top = end_y + DIRT_DEPTH.max(MAX_DECORATION_HEIGHT);
+        for y in (start_y - MAX_DECORATION_HEIGHT..=top).rev() {
+            let pos = glam::ivec3(x, y, z).as_dvec3();
+            if !self.is_terrain(pos, height) {
+                depth = 0;
+                air += 1;
+                continue;
+            }
+
+            let id = match depth {
+                0 => blocks.surface,
+                1..=DIRT_DEPTH => blocks.subsurface,
+                _ => self.blocks.stone,
+            };
+
+            if y < end_y && (y >= start_y || depth == 0) && !self.is_cave(pos) {
+                if y >= start_y {
+                    set(y, id);
+                    if depth == 0 && surface_y.is_none() {
+                        surface_y = Some(y);
+                    }
+                }
+
+                // Goes on the surface if there's room for it
+                if let Some((decoration, decoration_height)) = decoration {
+                    if depth == 0 && air >= decoration_height {
+                        let decoration_end = (y + decoration_height + 1).min(end_y);
+                        for y in (y + 1).max(start_y)..decoration_end {
+                            set(y, decoration);
+                        }
+                    }
+                }
+            }
+
+            depth = depth.saturating_add(1);
+            air = 0;
+        }
+
+        surface_y.zip(structure)
+    }
+
+    /// Gets the height of the surface by blending the heights of the biomes using their weights
+    fn height(&self, x: i32, z: i32, weights: &[f64; 5]) -> f64 {
+        let noise = self.height.get([x as f64, z as f64]);
+        Biome::ALL
+            .iter()
+            .zip(weights)
+            .map(|(biome, weight)| {
+                let params = BiomeParams::get(*biome);
+                weight * (params.base_height + noise * params.height_variation)
+            })
+            .sum()
+    }
+
+    /// Picks the biome that decides the blocks of a column, randomly between the biomes that
+    /// apply so the borders between them are blended
+    fn surface_biome(&self, x: i32, z: i32, weights: &[f64; 5]) -> Biome {
+        let mut random = self.column_random(x, z, 0);
+        for (biome, weight) in Biome::ALL.iter().zip(weights) {
+            if random < *weight {
+                return *biome;
+            }
+            random -= weight;
+        }
+        self.biome_at(x, z)
+    }
+
+    /// Gets the block and height of the decoration that goes on top of a column if it has one
+    fn decoration(&self, x: i32, z: i32, biome: Biome) -> Option<(BlockID, i32)> {
+        let params = BiomeParams::get(biome).decoration.as_ref()?;
+        if self.column_random(x, z, 1) >= params.chance {
+            return None;
+        }
+
+        let height = 1 + (self.column_random(x, z, 2) * params.max_height as f64) as i32;
+        Some((self.blocks.biomes[biome as usize].decoration?, height))
+    }
+
+    /// Gets the structure that goes on top of a column if it has one
+    fn structure(&self, x: i32, z: i32, biome: Biome) -> Option<&Structure> {
+        let structures = &self.blocks.biomes[biome as usize].structures;
+        structures
+            .iter()
+            .enumerate()
+            .find(|(i, (_, chance))| self.column_random(x, z, 3 + *i as u64) < *chance)
+            .map(|(_, (structure, _))| structure)
+    }
+
+    /// Gets a random number from 0 to 1 that is always the same for a column and salt
+    fn column_random(&self, x: i32, z: i32, salt: u64) -> f64 {
+        let column = (x as u32 as u64) << 32 | z as u32 as u64;
+        let hash = mix(self.seed ^ mix(column).wrapping_add(salt));
+        // The top 53 bits fit exactly into a f64
+        (hash >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Whether the position is below the surface before the caves are carved out
+    fn is_terrain(&self, pos: glam::DVec3, height: f64) -> bool {
+        // The 3D noise can only move the surface so far, so it doesn't need to be sampled far
+        // away from it
+        if pos.y >= height + OVERHANG {
+            return false;
+        } else if pos.y < height - OVERHANG {
+            return true;
+        }
+
+        let detail = self.detail.get(pos.to_array()).clamp(-1.0, 1.0);
+        pos.y < height + detail * OVERHANG
+    }
+
+    fn is_cave(&self, pos: glam::DVec3) -> bool {
+        // Stretched vertically so tunnels are mostly flat
+        let pos = (pos * glam::dvec3(1.0, 2.0, 1.0) / 48.0).to_array();
+        let [a, b] = self.caves.each_ref().map(|noise| noise.get(pos));
+        a * a + b * b < CAVE_RADIUS * CAVE_RADIUS
+    }
+}
+
+/// Gets a different seed for each noise from the world seed
+fn noise_seed(seed: u64, index: u64) -> u32 {
+    mix(seed.wrapping_add(index.wrapping_mul(0x9e3779b97f4a7c15))) as u32
+}
+
+/// Scrambles the bits so that nearby numbers give unrelated results, uses splitmix64
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opencuboids_common::{iter_3d, iter_3d_vec};
+
+    fn blocks(chunk: &Chunk) -> Vec<BlockID> {
+        iter_3d(0, CHUNK_SIZE as i32)
+            .map(|pos| chunk.get_block(pos.as_uvec3()))
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_world() {
+        let registry = BlockRegistry::default();
+        let world_gen = WorldGen::new(1234, &registry).unwrap();
+        let chunk_pos = block_to_chunk_pos(glam::ivec3(0, world_gen.surface_height(0, 0), 0));
+        let chunk = blocks(&world_gen.gen_chunk(chunk_pos).0);
+
+        // Nothing is kept between runs other than the seed
+        let world_gen = WorldGen::new(1234, &registry).unwrap();
+        assert_eq!(blocks(&world_gen.gen_chunk(chunk_pos).0), chunk);
+
+        let world_gen = WorldGen::new(1235, &registry).unwrap();
+        assert_ne!(blocks(&world_gen.gen_chunk(chunk_pos).0), chunk);
+    }
+
+    #[test]
+    fn same_across_chunk_borders() {
+        let registry = BlockRegistry::default();
+        let world_gen = WorldGen::new(42, &registry).unwrap();
+        let grass = registry.id("grass").unwrap();
+
+        // Chunks around the surface where the dirt and grass go over the borders
+        let center = block_to_chunk_pos(glam::ivec3(0, world_gen.surface_height(0, 0), 0));
+        for chunk_pos in iter_3d_vec(center - 1, center + 1) {
+            let (chunk, _) = world_gen.gen_terrain(chunk_pos);
+            for local_pos in iter_3d(0, CHUNK_SIZE as i32) {
+                let pos = chunk_pos * CHUNK_SIZE as i32 + local_pos;
+                let id = chunk.get_block(local_pos.as_uvec3());
+                assert_eq!(id, world_gen.block_at(pos), "Different block at {}", pos);
+
+                // The block above can be in the chunk above
+                if id == grass {
+                    assert!(!registry.is_solid(world_gen.block_at(pos + glam::IVec3::Y)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn caves_underground() {
+        let registry = BlockRegistry::default();
+        let world_gen = WorldGen::new(42, &registry).unwrap();
+
+        // Deep below the lowest the surface can go
+        let caves = iter_3d_vec(glam::ivec3(0, -4, 0), glam::ivec3(2, -3, 2))
+            .map(|chunk_pos| world_gen.gen_chunk(chunk_pos).0)
+            .flat_map(|chunk| blocks(&chunk))
+            .filter(|id| *id == AIR)
+            .count();
+        assert!(caves > 0 && caves < CHUNK_SIZE.pow(3) * 4 / 10);
+    }
+
+    #[test]
+    fn biomes() {
+        let registry = BlockRegistry::default();
+        let world_gen = WorldGen::new(7, &registry).unwrap();
+        let (mut columns, mut matching) = (0, 0);
+        for x in (-8192..8192).step_by(64) {
+            for z in (-8192..8192).step_by(512) {
+                // Blended at the borders without any cliffs
+                let weights = world_gen.climate.weights(x, z);
+                let next_weights = world_gen.climate.weights(x + 1, z);
+                let height = world_gen.height(x, z, &weights);
+                assert!((height - world_gen.height(x + 1, z, &next_weights)).abs() < 3.0);
+
+                // Away from the borders the surface is made of the biome blocks
+                let biome = world_gen.biome_at(x, z);
+                if weights[biome as usize] == 1.0 {
+                    let surface_pos = glam::ivec3(x, world_gen.surface_height(x, z), z);
+                    let top = world_gen.block_at(surface_pos);
+                    let blocks = &world_gen.blocks.biomes[biome as usize];
+                    columns += 1;
+                    if top == blocks.surface || Some(top) == blocks.decoration {
+                        matching += 1;
+                    }
+                }
+            }
+        }
+
+        // Except where caves come out of the surface
+        assert!(columns > 1000 && matching > columns * 95 / 100);
+    }
+
+    /// Generates the chunks in order the same way as the server, keeping the parts of structures
+    /// for chunks that haven't been generated yet
+    fn gen_area(
+        world_gen: &WorldGen,
+        order: impl Iterator<Item = glam::IVec3>,
+    ) -> (HashMap<glam::IVec3, Chunk>, usize) {
+        let mut chunks = HashMap::new();
+        let mut pending = HashMap::<glam::IVec3, Vec<_>>::new();
+        let mut outside_count = 0;
+        for chunk_pos in order {
+            let (mut chunk, outside_blocks) = world_gen.gen_chunk(chunk_pos);
+            for (pos, id) in pending.remove(&chunk_pos).unwrap_or_default() {
+                world_gen.place_structure_block(&mut chunk, pos, id);
+            }
+            chunks.insert(chunk_pos, chunk);
+
+            outside_count += outside_blocks.len();
+            for (pos, id) in outside_blocks {
+                match chunks.get_mut(&block_to_chunk_pos(pos)) {
+                    Some(chunk) => {
+                        world_gen.place_structure_block_in_generated(chunk, pos, id);
+                    }
+                    None => pending
+                        .entry(block_to_chunk_pos(pos))
+                        .or_default()
+                        .push((pos, id)),
+                }
+            }
+        }
+        (chunks, outside_count)
+    }
+
+    #[test]
+    fn structures_across_chunk_borders() {
+        let registry = BlockRegistry::default();
+        let world_gen = WorldGen::new(3, &registry).unwrap();
+
+        // Somewhere in the middle of a forest
+        let (x, z) = (-8192..8192)
+            .step_by(64)
+            .flat_map(|x| (-8192..8192).step_by(64).map(move |z| (x, z)))
+            .find(|(x, z)| world_gen.climate.weights(*x, *z)[Biome::Forest as usize] == 1.0)
+            .unwrap();
+        let center = block_to_chunk_pos(glam::ivec3(x, world_gen.surface_height(x, z), z));
+        let area = iter_3d_vec(center - 1, center + 2).collect::<Vec<_>>();
+
+        // Same blocks when generated backwards
+        let (chunks, outside_count) = gen_area(&world_gen, area.iter().copied());
+        let (reversed, _) = gen_area(&world_gen, area.iter().rev().copied());
+        for chunk_pos in iter_3d_vec(center - 1, center + 2) {
+            assert_eq!(blocks(&chunks[&chunk_pos]), blocks(&reversed[&chunk_pos]));
+        }
+
+        let log = registry.id("log").unwrap();
+        assert!(outside_count > 0);
+        assert!(blocks(&chunks[&center]).contains(&log));
+    }
+
+    #[test]
+    fn structures_keep_player_changes() {
+        let registry = BlockRegistry::default();
+        let world_gen = WorldGen::new(3, &registry).unwrap();
+        let stone = registry.id("stone").unwrap();
+        let leaves = registry.id("leaves").unwrap();
+
+        // A chunk on the surface with air above ground
+        let surface = world_gen.surface_height(0, 0);
+        let (mut chunk, _) = world_gen.gen_chunk(block_to_chunk_pos(glam::ivec3(0, surface, 0)));
+        let positions = iter_3d(0, CHUNK_SIZE as i32)
+            .map(|pos| chunk.pos * CHUNK_SIZE as i32 + pos)
+            .collect::<Vec<_>>();
+        let find = |id| {
+            positions
+                .iter()
+                .copied()
+                .filter(|pos| chunk.get_block(block_to_local_pos(*pos)) == id)
+                .collect::<Vec<_>>()
+        };
+        let (placed, untouched) = (find(AIR)[0], find(AIR)[1]);
+        let dug = find(stone)[0];
+        chunk.set_block(block_to_local_pos(placed), stone);
+        chunk.set_block(block_to_local_pos(dug), AIR);
+
+        // Structures don't go into blocks players placed or holes they dug
+        assert!(!world_gen.place_structure_block_in_generated(&mut chunk, placed, leaves));
+        assert!(!world_gen.place_structure_block_in_generated(&mut chunk, dug, leaves));
+        assert_eq!(chunk.get_block(block_to_local_pos(placed)), stone);
+        assert_eq!(chunk.get_block(block_to_local_pos(dug)), AIR);
+        assert!(world_gen.place_structure_block_in_generated(&mut chunk, untouched, leaves));
+        assert_eq!(chunk.get_block(block_to_local_pos(untouched)), leaves);
+    }
+}
@@ -0,0 +1,116 @@
+use opencuboids_common::{BlockID, CHUNK_SIZE};
+
+/// The blocks of a structure drawn as layers of characters
+pub struct Template {
+    /// Block names of the characters used in the layers, spaces are left as they are
+    pub legend: &'static [(char, &'static str)],
+    /// Layers from the bottom up, each row goes along the z axis and each column along the x
+    pub layers: &'static [&'static [&'static str]],
+    /// Position in the layers that goes right above the surface block
+    pub origin: [i32; 3],
+}
+
+pub const OAK_TREE: Template = Template {
+    legend: &[('L', "log"), ('#', "leaves")],
+    layers: &[
+        &["     ", "     ", "  L  ", "     ", "     "],
+        &["     ", "     ", "  L  ", "     ", "     "],
+        &["     ", "     ", "  L  ", "     ", "     "],
+        &[" ### ", "#####", "##L##", "#####", " ### "],
+        &[" ### ", "#####", "##L##", "#####", " ### "],
+        &["     ", " ### ", " #L# ", " ### ", "     "],
+        &["     ", "  #  ", " ### ", "  #  ", "     "],
+    ],
+    origin: [2, 0, 2],
+};
+
+pub const SPRUCE_TREE: Template = Template {
+    legend: &[('L', "log"), ('#', "leaves")],
+    layers: &[
+        &["     ", "     ", "  L  ", "     ", "     "],
+        &["     ", "     ", "  L  ", "     ", "     "],
+        &[" ### ", "#####", "##L##", "#####", " ### "],
+        &["     ", " ### ", " #L# ", " ### ", "     "],
+        &[" ### ", "#####", "##L##", "#####", " ### "],
+        &["     ", " ### ", " #L# ", " ### ", "     "],
+        &["     ", "  #  ", " #L# ", "  #  ", "     "],
+        &["     ", "     ", "  #  ", "     ", "     "],
+    ],
+    origin: [2, 0, 2],
+};
+
+/// The bottom layer goes into the surface to fill in the gaps under it on slopes
+pub const BOULDER: Template = Template {
+    legend: &[('#', "stone")],
+    layers: &[
+        &[" ## ", "####", "####", " ## "],
+        &[" ## ", "####", "### ", "    "],
+        &["    ", " #  ", "    ", "    "],
+    ],
+    origin: [1, 1, 1],
+};
+
+/// The blocks of a template with the offsets from the block above the surface
+pub struct Structure {
+    pub blocks: Vec<(glam::IVec3, BlockID)>,
+}
+
+impl Structure {
+    pub fn new(
+        template: &Template,
+        mut get: impl FnMut(&'static str) -> std::io::Result<BlockID>,
+    ) -> std::io::Result<Self> {
+        let origin = glam::IVec3::from(template.origin);
+        let mut blocks = Vec::new();
+        for (y, layer) in template.layers.iter().enumerate() {
+            for (z, row) in layer.iter().enumerate() {
+                for (x, c) in row.chars().enumerate().filter(|(_, c)| *c != ' ') {
+                    let Some((_, name)) = template.legend.iter().find(|(key, _)| *key == c) else {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("structure uses {} which isn't in its legend", c),
+                        ));
+                    };
+
+                    let offset = glam::ivec3(x as i32, y as i32, z as i32) - origin;
+                    blocks.push((offset, get(name)?));
+                }
+            }
+        }
+
+        // Structures only reach into the chunks right next to the one they start in
+        assert!(blocks
+            .iter()
+            .all(|(offset, _)| offset.abs().max_element() < CHUNK_SIZE as i32));
+        Ok(Self { blocks })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn template_blocks() {
+        let names = ["log", "leaves", "stone"];
+        let get = |name| Ok(names.iter().position(|x| *x == name).unwrap() as BlockID);
+
+        let tree = Structure::new(&OAK_TREE, get).unwrap();
+        let logs = tree.blocks.iter().filter(|(_, id)| *id == 0);
+        assert!(logs
+            .map(|(offset, _)| *offset)
+            .eq((0..6).map(|y| glam::ivec3(0, y, 0))));
+        assert_eq!(tree.blocks.len(), 6 + 20 * 2 + 8 + 5);
+
+        // The bottom layer of the boulder goes into the surface
+        let boulder = Structure::new(&BOULDER, get).unwrap();
+        assert_eq!(boulder.blocks[0].0, glam::ivec3(0, -1, -1));
+
+        let invalid = Template {
+            legend: &[],
+            layers: &[&["L"]],
+            origin: [0, 0, 0],
+        };
+        assert!(Structure::new(&invalid, get).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use opencuboids_common::{block_to_chunk_pos, BlockID, Chunk};
+use serde::{Deserialize, Serialize};
+
+use crate::region::{region_index, region_pos, RegionFile};
+
+/// Information about the world stored in level.ron
+#[derive(Serialize, Deserialize)]
+struct Level {
+    seed: u64,
+}
+
+/// Stores chunks on disk grouped into region files inside of the world directory
+pub struct WorldSave {
+    dir: PathBuf,
+    level: Level,
+    regions: HashMap<glam::IVec3, RegionFile>,
+    /// Chunks that have changed since the last flush
+    dirty_chunks: HashMap<glam::IVec3, Chunk>,
+    /// Blocks of structures waiting for the chunk they go in to be generated, stored in
+    /// pending.bin
+    pending_blocks: HashMap<glam::IVec3, Vec<(glam::IVec3, BlockID)>>,
+    pending_dirty: bool,
+}
+
+impl WorldSave {
+    pub fn open(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(dir.join("regions"))?;
+
+        let level_path = dir.join("level.ron");
+        let level = if level_path.exists() {
+            ron::from_str(&std::fs::read_to_string(&level_path)?)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?
+        } else {
+            let level = Level {
+                seed: random_seed(),
+            };
+            let source = ron::ser::to_string_pretty(&level, Default::default())
+                .map_err(std::io::Error::other)?;
+            std::fs::write(&level_path, source)?;
+            level
+        };
+
+        let pending_path = dir.join("pending.bin");
+        let pending_blocks = if pending_path.exists() {
+            bincode::deserialize(&std::fs::read(&pending_path)?)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self {
+            dir,
+            level,
+            regions: Default::default(),
+            dirty_chunks: Default::default(),
+            pending_blocks,
+            pending_dirty: false,
+        })
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.level.seed
+    }
+
+    /// Loads the chunk from memory if it hasn't been flushed yet otherwise from disk
+    pub fn load_chunk(&mut self, chunk_pos: glam::IVec3) -> std::io::Result<Option<Chunk>> {
+        if let Some(chunk) = self.dirty_chunks.get(&chunk_pos) {
+            return Ok(Some(chunk.clone()));
+        }
+
+        let region = self.region(chunk_pos)?;
+        match region.read(region_index(chunk_pos))? {
+            Some(data) => {
+                let chunk = bincode::deserialize::<Chunk>(&data)
+                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+                Ok(Some(chunk))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Marks the chunk as needing to be written on the next flush
+    pub fn save_chunk(&mut self, chunk: Chunk) {
+        self.dirty_chunks.insert(chunk.pos, chunk);
+    }
+
+    /// Keeps blocks at world positions until the chunks they go in get generated
+    pub fn add_pending_blocks(&mut self, blocks: impl IntoIterator<Item = (glam::IVec3, BlockID)>) {
+        for (pos, id) in blocks {
+            self.pending_blocks
+                .entry(block_to_chunk_pos(pos))
+                .or_default()
+                .push((pos, id));
+            self.pending_dirty = true;
+        }
+    }
+
+    /// Removes the blocks waiting for the chunk to be generated
+    pub fn take_pending_blocks(&mut self, chunk_pos: glam::IVec3) -> Vec<(glam::IVec3, BlockID)> {
+        let blocks = self.pending_blocks.remove(&chunk_pos).unwrap_or_default();
+        self.pending_dirty |= !blocks.is_empty();
+        blocks
+    }
+
+    /// Writes all the dirty chunks to their region files along with the pending blocks
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if self.pending_dirty {
+            // Written to another file first so a failed write doesn't lose the old blocks
+            let data = bincode::serialize(&self.pending_blocks).map_err(std::io::Error::other)?;
+            let temp_path = self.dir.join("pending.bin.tmp");
+            std::fs::write(&temp_path, data)?;
+            std::fs::rename(temp_path, self.dir.join("pending.bin"))?;
+            self.pending_dirty = false;
+        }
+
+        if self.dirty_chunks.is_empty() {
+            return Ok(());
+        }
+
+        log::info!("Saving {} chunks", self.dirty_chunks.len());
+
+        let mut encoded_chunks = Vec::with_capacity(self.dirty_chunks.len());
+        for (chunk_pos, chunk) in &self.dirty_chunks {
+            let data = bincode::serialize(chunk).map_err(std::io::Error::other)?;
+            encoded_chunks.push((*chunk_pos, data));
+        }
+
+        let mut touched_regions = HashSet::new();
+        for (chunk_pos, data) in encoded_chunks {
+            self.region(chunk_pos)?
+                .write(region_index(chunk_pos), &data)?;
+            touched_regions.insert(region_pos(chunk_pos));
+        }
+
+        for pos in touched_regions {
+            self.regions.get_mut(&pos).unwrap().sync()?;
+        }
+
+        // Only clear after everything was written so a failed flush can be retried
+        self.dirty_chunks.clear();
+        Ok(())
+    }
+
+    fn region(&mut self, chunk_pos: glam::IVec3) -> std::io::Result<&mut RegionFile> {
+        let pos = region_pos(chunk_pos);
+        if !self.regions.contains_key(&pos) {
+            let path = self
+                .dir
+                .join("regions")
+                .join(format!("r.{}.{}.{}.ocr", pos.x, pos.y, pos.z));
+            self.regions.insert(pos, RegionFile::open(&path)?);
+        }
+
+        Ok(self.regions.get_mut(&pos).unwrap())
+    }
+}
+
+/// Gets an empty directory for a test world that no other test uses, including the same test
+/// running in another process at the same time
+#[cfg(test)]
+pub fn temp_world_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join("opencuboids-test").join(format!(
+        "{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn random_seed() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+
+    // RandomState is seeded randomly by the OS
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use opencuboids_common::Light;
+
+    use super::*;
+
+    #[test]
+    fn chunks_saved() {
+        let dir = temp_world_dir("chunks-saved");
+
+        // Chunks in different regions, including negative ones
+        let chunk_positions = [
+            glam::ivec3(0, 0, 0),
+            glam::ivec3(3, 1, 15),
+            glam::ivec3(-1, -1, -1),
+            glam::ivec3(16, -20, 40),
+        ];
+        let chunk_at = |chunk_pos: glam::IVec3, id: BlockID| {
+            let mut chunk = Chunk::new(chunk_pos);
+            chunk.set_block(glam::uvec3(1, 2, 3), id);
+            chunk.set_light(glam::uvec3(4, 5, 6), Light::new(id as u8, 3));
+            chunk
+        };
+        let assert_chunk = |world_save: &mut WorldSave, chunk_pos: glam::IVec3, id: BlockID| {
+            let chunk = world_save.load_chunk(chunk_pos).unwrap().unwrap();
+            assert_eq!(chunk.pos, chunk_pos);
+            assert_eq!(chunk.get_block(glam::uvec3(1, 2, 3)), id);
+            assert_eq!(chunk.get_block(glam::uvec3(0, 0, 0)), 0);
+            assert_eq!(
+                chunk.get_light(glam::uvec3(4, 5, 6)),
+                Light::new(id as u8, 3)
+            );
+        };
+
+        let mut world_save = WorldSave::open(&dir).unwrap();
+        let seed = world_save.seed();
+        for (i, chunk_pos) in chunk_positions.into_iter().enumerate() {
+            world_save.save_chunk(chunk_at(chunk_pos, i as BlockID + 1));
+        }
+        // Loaded from memory before the flush
+        assert_chunk(&mut world_save, chunk_positions[1], 2);
+        world_save.flush().unwrap();
+
+        // Rewriting a chunk with more blocks doesn't fit into its old slot
+        let mut bigger = chunk_at(chunk_positions[0], 9);
+        for x in 0..32 {
+            bigger.set_block(glam::uvec3(x, 31, x), x as BlockID + 10);
+        }
+        world_save.save_chunk(bigger);
+        world_save.flush().unwrap();
+
+        let mut world_save = WorldSave::open(&dir).unwrap();
+        assert_eq!(world_save.seed(), seed);
+        let chunk = world_save.load_chunk(chunk_positions[0]).unwrap().unwrap();
+        assert_eq!(chunk.get_block(glam::uvec3(1, 2, 3)), 9);
+        assert_eq!(chunk.get_block(glam::uvec3(31, 31, 31)), 41);
+        for (i, chunk_pos) in chunk_positions.into_iter().enumerate().skip(1) {
+            assert_chunk(&mut world_save, chunk_pos, i as BlockID + 1);
+        }
+        assert!(world_save
+            .load_chunk(glam::ivec3(0, 1, 0))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn pending_blocks_saved() {
+        let dir = temp_world_dir("pending-blocks");
+
+        let mut world_save = WorldSave::open(&dir).unwrap();
+        world_save.add_pending_blocks([(glam::ivec3(-1, 40, 3), 7), (glam::ivec3(5, 2, 1), 8)]);
+        world_save.flush().unwrap();
+
+        // Still there after the world is opened again until they get taken
+        let mut world_save = WorldSave::open(&dir).unwrap();
+        assert_eq!(
+            world_save.take_pending_blocks(glam::ivec3(-1, 1, 0)),
+            [(glam::ivec3(-1, 40, 3), 7)]
+        );
+        assert!(world_save
+            .take_pending_blocks(glam::ivec3(-1, 1, 0))
+            .is_empty());
+        world_save.flush().unwrap();
+
+        let mut world_save = WorldSave::open(&dir).unwrap();
+        assert!(world_save
+            .take_pending_blocks(glam::ivec3(-1, 1, 0))
+            .is_empty());
+        assert_eq!(world_save.take_pending_blocks(glam::IVec3::ZERO).len(), 1);
+    }
+}